@@ -0,0 +1,616 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+// Contract of a command shared by the backend and the frontend.
+// `NAME` must be the name of the `#[tauri::command]` function.
+pub trait Command {
+    const NAME: &'static str;
+    type Args: Serialize + DeserializeOwned;
+    type Response: Serialize + DeserializeOwned;
+}
+
+pub type Args<C> = <C as Command>::Args;
+pub type Response<C> = Result<<C as Command>::Response, Error>;
+
+// Every command receives its arguments under this key.
+pub const ARGS_KEY: &str = "args";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Io,
+    NotFound,
+    UnsupportedFormat,
+    Decode,
+    // There is no image left in the traversal direction
+    NoMoreImages,
+    RootReached,
+    // Navigation would leave the traversal root
+    EndOfCollection,
+    // The app has been started without any path
+    NothingOpened,
+    // The collection index has not reached the requested position yet
+    Indexing,
+    PoisonedLock,
+    // A newer request has been sent, so the response is no longer needed
+    Superseded,
+    InvalidArgument,
+    Internal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Name,
+    // "img2" before "img10", ignoring letter cases
+    #[default]
+    Natural,
+    // Collated by the system locale, where letter cases only break ties
+    CaseInsensitive,
+    Modified,
+    Created,
+    Size,
+    ExifDate,
+    Random,
+}
+
+static SORT_KEYS: [(&str, SortKey); 8] = [
+    ("name", SortKey::Name),
+    ("natural", SortKey::Natural),
+    ("case-insensitive", SortKey::CaseInsensitive),
+    ("mtime", SortKey::Modified),
+    ("ctime", SortKey::Created),
+    ("size", SortKey::Size),
+    ("exif-date", SortKey::ExifDate),
+    ("random", SortKey::Random),
+];
+
+impl SortKey {
+    pub fn name(self) -> &'static str {
+        SORT_KEYS.iter().find(|(_, key)| *key == self).unwrap().0
+    }
+
+    // Cycles through the keys in the order of `SORT_KEYS`
+    pub fn next(self) -> Self {
+        let index = SORT_KEYS.iter().position(|(_, key)| *key == self).unwrap();
+        SORT_KEYS[(index + 1) % SORT_KEYS.len()].1
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SortMode {
+    pub key: SortKey,
+    pub order: SortOrder,
+}
+
+impl SortMode {
+    pub fn reversed(self) -> Self {
+        let order = match self.order {
+            SortOrder::Ascending => SortOrder::Descending,
+            SortOrder::Descending => SortOrder::Ascending,
+        };
+        Self { order, ..self }
+    }
+}
+
+// Parses "<key>[:asc|:desc]" (e.g. "mtime:desc")
+impl FromStr for SortMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        let (key, order) = match mode.split_once(':') {
+            Some((key, "asc")) => (key, SortOrder::Ascending),
+            Some((key, "desc")) => (key, SortOrder::Descending),
+            Some((_, order)) => return Err(format!("Unknown sort order: {:?}", order)),
+            None => (mode, SortOrder::Ascending),
+        };
+        match SORT_KEYS.iter().find(|(name, _)| *name == key) {
+            Some((_, key)) => Ok(Self { key: *key, order }),
+            None => Err(format!("Unknown sort key: {:?}", key)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SortConfig {
+    pub files: SortMode,
+    pub directories: SortMode,
+    // Seed of the random order
+    pub seed: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortTarget {
+    Files,
+    Directories,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortChange {
+    Set(SortMode),
+    NextKey,
+    Reverse,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JumpTarget {
+    // Ends of the current directory
+    First,
+    Last,
+    // Ends of the whole collection
+    FirstInCollection,
+    LastInCollection,
+    // Zero-based position in the recursive index, or in the current directory
+    Position(usize),
+    Percent(f64),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FitMode {
+    // The whole image is shown
+    #[default]
+    Contain,
+    Width,
+    Height,
+    // Scaled by the zoom only
+    Original,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ViewSettings {
+    pub fit: FitMode,
+    // Scale relative to the fitted size
+    pub zoom: f64,
+}
+
+impl Default for ViewSettings {
+    fn default() -> Self {
+        Self {
+            fit: FitMode::default(),
+            zoom: 1.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingDirection {
+    #[default]
+    LeftToRight,
+    // The next page is on the left, as in manga
+    RightToLeft,
+}
+
+impl ReadingDirection {
+    pub fn reversed(self) -> Self {
+        match self {
+            Self::LeftToRight => Self::RightToLeft,
+            Self::RightToLeft => Self::LeftToRight,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpreadSettings {
+    // Two images are shown side by side, except for wide ones
+    pub enabled: bool,
+    pub direction: ReadingDirection,
+    // The first image of each directory is shown alone
+    pub cover: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpreadChange {
+    Toggle,
+    ReverseDirection,
+    ToggleCover,
+}
+
+impl SpreadSettings {
+    pub fn apply(self, change: SpreadChange) -> Self {
+        match change {
+            SpreadChange::Toggle => Self {
+                enabled: !self.enabled,
+                ..self
+            },
+            SpreadChange::ReverseDirection => Self {
+                direction: self.direction.reversed(),
+                ..self
+            },
+            SpreadChange::ToggleCover => Self {
+                cover: !self.cover,
+                ..self
+            },
+        }
+    }
+}
+
+// Horizontal flip followed by clockwise quarter turns, as the image is displayed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(from = "RawTransform")]
+pub struct Transform {
+    pub quarter_turns: u8,
+    pub flipped: bool,
+}
+
+// Transforms sent from the other side may have any number of turns
+#[derive(Deserialize)]
+struct RawTransform {
+    quarter_turns: u8,
+    flipped: bool,
+}
+
+impl From<RawTransform> for Transform {
+    fn from(raw: RawTransform) -> Self {
+        Self::new(raw.quarter_turns, raw.flipped)
+    }
+}
+
+// Transforms of the EXIF orientation values 1 to 8
+static ORIENTATIONS: [Transform; 8] = [
+    Transform::new(0, false),
+    Transform::new(0, true),
+    Transform::new(2, false),
+    Transform::new(2, true),
+    Transform::new(3, true),
+    Transform::new(1, false),
+    Transform::new(1, true),
+    Transform::new(3, false),
+];
+
+impl Transform {
+    pub const fn new(quarter_turns: u8, flipped: bool) -> Self {
+        Self {
+            quarter_turns: quarter_turns % 4,
+            flipped,
+        }
+    }
+
+    // `self` followed by `next`
+    pub fn then(self, next: Self) -> Self {
+        // Flipping reverses the preceding rotation
+        let (turns, next_turns) = (self.quarter_turns % 4, next.quarter_turns % 4);
+        let turns = match next.flipped {
+            true => next_turns + 4 - turns,
+            false => next_turns + turns,
+        };
+        Self::new(turns, self.flipped != next.flipped)
+    }
+
+    pub fn rotate_cw(self) -> Self {
+        self.then(Self::new(1, false))
+    }
+
+    pub fn rotate_ccw(self) -> Self {
+        self.then(Self::new(3, false))
+    }
+
+    pub fn rotate_180(self) -> Self {
+        self.then(Self::new(2, false))
+    }
+
+    pub fn flip_horizontal(self) -> Self {
+        self.then(Self::new(0, true))
+    }
+
+    pub fn flip_vertical(self) -> Self {
+        self.then(Self::new(2, true))
+    }
+
+    // Whether the width and the height are swapped
+    pub fn is_transposed(self) -> bool {
+        self.quarter_turns % 2 == 1
+    }
+
+    // Unknown values are treated as the upright orientation
+    pub fn from_orientation(orientation: u16) -> Self {
+        match orientation {
+            1..=8 => ORIENTATIONS[orientation as usize - 1],
+            _ => Self::default(),
+        }
+    }
+
+    pub fn orientation(self) -> u16 {
+        ORIENTATIONS.iter().position(|t| *t == self).unwrap() as u16 + 1
+    }
+}
+
+// Where a move has landed after wrapping around the ends of the collection
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Wrap {
+    ToFirst,
+    ToLast,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ImageSource {
+    pub url: String,
+    pub path: PathBuf,
+    pub file_name: String,
+    // Position in the current directory
+    pub index: usize,
+    pub count: usize,
+    // Position in the whole collection, if known
+    pub position: Option<usize>,
+    pub total: Option<usize>,
+    pub byte_size: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub mimetype: String,
+    // Stored in the served image, which is turned upright by the webview
+    pub orientation: Transform,
+    // Milliseconds since the unix epoch
+    pub modified: Option<u64>,
+    pub sort: SortConfig,
+    pub wrapped: Option<Wrap>,
+    pub spread: SpreadSettings,
+    // Next image shown beside this one in the spread mode
+    pub facing: Option<Box<ImageSource>>,
+}
+
+// Request ids restart from this whenever the webview is loaded
+pub const FIRST_REQUEST_ID: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MoveArgs {
+    pub moves: usize,
+    pub request_id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RequestArgs {
+    pub request_id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SortArgs {
+    pub target: SortTarget,
+    pub change: SortChange,
+    pub request_id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpreadArgs {
+    pub change: SpreadChange,
+    pub request_id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JumpArgs {
+    pub target: JumpTarget,
+    pub request_id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OpenArgs {
+    pub paths: Vec<PathBuf>,
+    pub request_id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListArgs {
+    // The home directory is listed by default
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DirectoryListing {
+    pub path: PathBuf,
+    pub parent: Option<PathBuf>,
+    // Archives are listed as directories
+    pub directories: Vec<PathBuf>,
+    // Image files only
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrientationArgs {
+    pub path: PathBuf,
+    // Applied to the image as it is displayed now
+    pub transform: Transform,
+}
+
+pub struct NextImage;
+
+impl Command for NextImage {
+    const NAME: &'static str = "next_image";
+    type Args = MoveArgs;
+    type Response = ImageSource;
+}
+
+pub struct PrevImage;
+
+impl Command for PrevImage {
+    const NAME: &'static str = "prev_image";
+    type Args = MoveArgs;
+    type Response = ImageSource;
+}
+
+pub struct NextDirectory;
+
+impl Command for NextDirectory {
+    const NAME: &'static str = "next_directory";
+    type Args = RequestArgs;
+    type Response = ImageSource;
+}
+
+pub struct PrevDirectory;
+
+impl Command for PrevDirectory {
+    const NAME: &'static str = "prev_directory";
+    type Args = RequestArgs;
+    type Response = ImageSource;
+}
+
+pub struct ChangeSort;
+
+impl Command for ChangeSort {
+    const NAME: &'static str = "change_sort";
+    type Args = SortArgs;
+    type Response = ImageSource;
+}
+
+pub struct ChangeSpread;
+
+impl Command for ChangeSpread {
+    const NAME: &'static str = "change_spread";
+    type Args = SpreadArgs;
+    type Response = ImageSource;
+}
+
+pub struct Jump;
+
+impl Command for Jump {
+    const NAME: &'static str = "jump";
+    type Args = JumpArgs;
+    type Response = ImageSource;
+}
+
+pub struct OpenPaths;
+
+impl Command for OpenPaths {
+    const NAME: &'static str = "open_paths";
+    type Args = OpenArgs;
+    type Response = ImageSource;
+}
+
+pub struct ListDirectory;
+
+impl Command for ListDirectory {
+    const NAME: &'static str = "list_directory";
+    type Args = ListArgs;
+    type Response = DirectoryListing;
+}
+
+pub struct SaveOrientation;
+
+impl Command for SaveOrientation {
+    const NAME: &'static str = "save_orientation";
+    type Args = OrientationArgs;
+    type Response = ();
+}
+
+pub struct GetView;
+
+impl Command for GetView {
+    const NAME: &'static str = "get_view";
+    type Args = ();
+    type Response = ViewSettings;
+}
+
+pub struct SetView;
+
+impl Command for SetView {
+    const NAME: &'static str = "set_view";
+    type Args = ViewSettings;
+    type Response = ();
+}
+
+#[test]
+fn test_sort_mode() {
+    assert_eq!(
+        "mtime:desc".parse::<SortMode>(),
+        Ok(SortMode {
+            key: SortKey::Modified,
+            order: SortOrder::Descending
+        })
+    );
+    assert_eq!(
+        "natural".parse::<SortMode>(),
+        Ok(SortMode {
+            key: SortKey::Natural,
+            order: SortOrder::Ascending
+        })
+    );
+    assert!("size:up".parse::<SortMode>().is_err());
+    assert!("date".parse::<SortMode>().is_err());
+    assert_eq!(SortMode::default().key, SortKey::Natural);
+
+    assert_eq!(SortKey::Name.next(), SortKey::Natural);
+    assert_eq!(SortKey::Random.next(), SortKey::Name);
+    assert_eq!(SortKey::ExifDate.name(), "exif-date");
+}
+
+#[test]
+fn test_spread_settings() {
+    let settings = SpreadSettings::default();
+    assert!(!settings.enabled);
+    assert_eq!(settings.direction, ReadingDirection::LeftToRight);
+
+    let settings = settings
+        .apply(SpreadChange::Toggle)
+        .apply(SpreadChange::ReverseDirection)
+        .apply(SpreadChange::ToggleCover);
+    assert_eq!(
+        settings,
+        SpreadSettings {
+            enabled: true,
+            direction: ReadingDirection::RightToLeft,
+            cover: true,
+        }
+    );
+    assert_eq!(
+        settings.apply(SpreadChange::ReverseDirection).direction,
+        ReadingDirection::LeftToRight
+    );
+}
+
+#[test]
+fn test_transform() {
+    let upright = Transform::default();
+    assert_eq!(upright.rotate_cw().orientation(), 6);
+    assert_eq!(upright.rotate_ccw().orientation(), 8);
+    assert_eq!(upright.rotate_180().orientation(), 3);
+    assert_eq!(upright.flip_horizontal().orientation(), 2);
+    assert_eq!(upright.flip_vertical().orientation(), 4);
+    assert_eq!(upright.flip_horizontal().rotate_ccw().orientation(), 5);
+    assert_eq!(upright.flip_horizontal().rotate_cw().orientation(), 7);
+
+    // Flipping after rotating is the same as rotating the other way after flipping
+    assert_eq!(
+        upright.rotate_cw().flip_horizontal(),
+        upright.flip_horizontal().rotate_ccw()
+    );
+    assert_eq!(upright.rotate_cw().rotate_ccw(), upright);
+    assert_eq!(upright.flip_vertical().flip_vertical(), upright);
+    for orientation in 1..=8 {
+        assert_eq!(
+            Transform::from_orientation(orientation).orientation(),
+            orientation
+        );
+    }
+    assert_eq!(Transform::from_orientation(0), upright);
+
+    let transform = Transform::from(RawTransform {
+        quarter_turns: 255,
+        flipped: true,
+    });
+    assert_eq!(transform, Transform::new(3, true));
+    assert_eq!(
+        Transform::new(0, true).then(transform).orientation(),
+        Transform::new(3, false).orientation()
+    );
+}
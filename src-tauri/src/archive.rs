@@ -0,0 +1,528 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{metadata, File};
+use std::io::{copy, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use flate2::read::GzDecoder;
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+use super::error::{ViewerError, ViewerResult};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+    SevenZip,
+}
+
+static ARCHIVE_SUFFIXES: [(&str, ArchiveKind); 10] = [
+    (".zip", ArchiveKind::Zip),
+    (".cbz", ArchiveKind::Zip),
+    (".tar", ArchiveKind::Tar),
+    (".cbt", ArchiveKind::Tar),
+    (".tar.gz", ArchiveKind::TarGz),
+    (".tgz", ArchiveKind::TarGz),
+    (".tar.zst", ArchiveKind::TarZst),
+    (".tzst", ArchiveKind::TarZst),
+    (".7z", ArchiveKind::SevenZip),
+    (".cb7", ArchiveKind::SevenZip),
+];
+
+fn get_archive_kind(path: &Path) -> Option<ArchiveKind> {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => {
+            let name = name.to_ascii_lowercase();
+            ARCHIVE_SUFFIXES
+                .iter()
+                .find(|(suffix, _)| name.ends_with(suffix))
+                .map(|(_, kind)| *kind)
+        }
+        None => None,
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    get_archive_kind(path).is_some() && path.is_file()
+}
+
+// Split a virtual path (e.g. "book.zip/chapter1/001.png") into the archive file and
+// the entry path inside it.
+pub fn split_archive_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    path.ancestors()
+        .find(|ancestor| is_archive(ancestor))
+        .map(|archive| {
+            (
+                archive.to_path_buf(),
+                path.strip_prefix(archive).unwrap().to_path_buf(),
+            )
+        })
+}
+
+// Entry names relative to the archive root, or `None` for the ones which would leave it.
+// Archives made of "." (e.g. `tar -cf x.tar .`) name their entries "./a.png".
+fn normalize_entry_name(name: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    match normalized.as_os_str().is_empty() {
+        true => None,
+        false => Some(normalized),
+    }
+}
+
+struct ArchiveEntry {
+    name: PathBuf,
+    // Zip: file index, Tar: data position in the archive, others: data position in the spill file
+    offset: u64,
+    size: u64,
+}
+
+enum EntryStorage {
+    // Entries are read through the zip central directory, which is parsed once
+    Zip(Mutex<ZipArchive<File>>),
+    // Entries are stored uncompressed in the archive itself
+    Archive,
+    // Entries of non-seekable archives are extracted once into a temporary file
+    Spill(Mutex<File>),
+}
+
+struct ArchiveIndex {
+    // Keyed by the entry name
+    entries: HashMap<PathBuf, ArchiveEntry>,
+    storage: EntryStorage,
+    modified: Option<SystemTime>,
+}
+
+fn get_modified(archive: &Path) -> Option<SystemTime> {
+    metadata(archive).and_then(|meta| meta.modified()).ok()
+}
+
+fn index_zip(archive: &Path) -> ViewerResult<(Vec<ArchiveEntry>, ZipArchive<File>)> {
+    let file = File::open(archive)?;
+    let mut zip = ZipArchive::new(file).map_err(ViewerError::decode)?;
+    let mut entries = vec![];
+    for index in 0..zip.len() {
+        match zip.by_index_raw(index) {
+            Ok(entry) if entry.is_file() => entries.push(ArchiveEntry {
+                name: PathBuf::from(entry.name()),
+                offset: index as u64,
+                size: entry.size(),
+            }),
+            Ok(_) => (),
+            Err(err) => log::info!("{:?}", err),
+        }
+    }
+    Ok((entries, zip))
+}
+
+fn index_tar(archive: &Path) -> ViewerResult<Vec<ArchiveEntry>> {
+    let file = File::open(archive)?;
+    let mut tar = tar::Archive::new(file);
+    let mut entries = vec![];
+    for entry in tar.entries()? {
+        match entry {
+            Ok(entry) if entry.header().entry_type().is_file() => match entry.path() {
+                Ok(name) => entries.push(ArchiveEntry {
+                    name: name.to_path_buf(),
+                    offset: entry.raw_file_position(),
+                    size: entry.size(),
+                }),
+                Err(err) => log::info!("{:?}", err),
+            },
+            Ok(_) => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(entries)
+}
+
+fn spill_entry<R: Read + ?Sized>(
+    spill: &mut File,
+    name: PathBuf,
+    reader: &mut R,
+) -> std::io::Result<ArchiveEntry> {
+    let offset = spill.seek(SeekFrom::End(0))?;
+    let size = copy(reader, spill)?;
+    Ok(ArchiveEntry { name, offset, size })
+}
+
+fn spill_tar<R: Read>(reader: R, spill: &mut File) -> ViewerResult<Vec<ArchiveEntry>> {
+    let mut tar = tar::Archive::new(reader);
+    let mut entries = vec![];
+    for entry in tar.entries()? {
+        match entry {
+            Ok(mut entry) if entry.header().entry_type().is_file() => {
+                let name = match entry.path() {
+                    Ok(name) => name.to_path_buf(),
+                    Err(err) => return Err(err.into()),
+                };
+                match spill_entry(spill, name, &mut entry) {
+                    Ok(entry) => entries.push(entry),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Ok(_) => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(entries)
+}
+
+fn spill_7z(archive: &Path, spill: &mut File) -> ViewerResult<Vec<ArchiveEntry>> {
+    let mut reader = SevenZReader::open(archive, Password::empty()).map_err(ViewerError::decode)?;
+    let mut entries = vec![];
+    reader
+        .for_each_entries(|entry, data| {
+            if !entry.is_directory() {
+                entries.push(spill_entry(spill, PathBuf::from(entry.name()), data)?);
+            }
+            Ok(true)
+        })
+        .map_err(ViewerError::decode)?;
+    Ok(entries)
+}
+
+fn build_index(archive: &Path, kind: ArchiveKind) -> ViewerResult<ArchiveIndex> {
+    log::debug!("Build archive index for {:?} ({:?})", archive, kind);
+    let modified = get_modified(archive);
+    let (entries, storage) = match kind {
+        ArchiveKind::Zip => {
+            let (entries, zip) = index_zip(archive)?;
+            (entries, EntryStorage::Zip(Mutex::new(zip)))
+        }
+        ArchiveKind::Tar => (index_tar(archive)?, EntryStorage::Archive),
+        _ => {
+            let mut spill = tempfile::tempfile()?;
+            let file = File::open(archive)?;
+            let entries = match kind {
+                ArchiveKind::TarGz => spill_tar(GzDecoder::new(file), &mut spill)?,
+                ArchiveKind::TarZst => match zstd::stream::read::Decoder::new(file) {
+                    Ok(decoder) => spill_tar(decoder, &mut spill)?,
+                    Err(err) => return Err(err.into()),
+                },
+                _ => spill_7z(archive, &mut spill)?,
+            };
+            spill.flush()?;
+            (entries, EntryStorage::Spill(Mutex::new(spill)))
+        }
+    };
+
+    // Later entries of the same name replace the former ones, as tar does
+    let entries: HashMap<PathBuf, ArchiveEntry> = entries
+        .into_iter()
+        .filter_map(|entry| match normalize_entry_name(&entry.name) {
+            Some(name) => Some((name.clone(), ArchiveEntry { name, ..entry })),
+            None => None,
+        })
+        .collect();
+    log::debug!("{:?} entries are found in {:?}", entries.len(), archive);
+    Ok(ArchiveIndex {
+        entries,
+        storage,
+        modified,
+    })
+}
+
+// Archives whose indices are kept. The spill files are removed with the indices.
+const MAX_ARCHIVE_INDICES: usize = 8;
+
+// Built at most once at a time for each archive
+type IndexSlot = Arc<Mutex<Option<Arc<ArchiveIndex>>>>;
+
+#[derive(Default)]
+struct IndexCache {
+    slots: HashMap<PathBuf, (IndexSlot, u64)>,
+    clock: u64,
+}
+
+impl IndexCache {
+    // Evicts the least recently used slots beyond the limit
+    fn slot(&mut self, archive: &Path) -> IndexSlot {
+        self.clock += 1;
+        let clock = self.clock;
+        let slot = match self.slots.get_mut(archive) {
+            Some((slot, last_used)) => {
+                *last_used = clock;
+                slot.clone()
+            }
+            None => {
+                let slot = IndexSlot::default();
+                self.slots
+                    .insert(archive.to_path_buf(), (slot.clone(), clock));
+                slot
+            }
+        };
+        while self.slots.len() > MAX_ARCHIVE_INDICES {
+            let oldest = self
+                .slots
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(path, _)| path.clone());
+            match oldest {
+                Some(path) => {
+                    log::debug!("Drop archive index of {:?}", path);
+                    self.slots.remove(&path);
+                }
+                None => break,
+            }
+        }
+        slot
+    }
+}
+
+fn archive_indices() -> &'static Mutex<IndexCache> {
+    static INDICES: OnceLock<Mutex<IndexCache>> = OnceLock::new();
+    INDICES.get_or_init(|| Mutex::new(IndexCache::default()))
+}
+
+fn get_index(archive: &Path) -> ViewerResult<Arc<ArchiveIndex>> {
+    let kind = match get_archive_kind(archive) {
+        Some(kind) => kind,
+        None => return Err(ViewerError::UnsupportedFormat(archive.to_path_buf())),
+    };
+
+    // Only the reads of the same archive wait for its index to be built
+    let slot = archive_indices().lock()?.slot(archive);
+    let mut slot = slot.lock()?;
+    match slot.as_ref() {
+        Some(index) if index.modified == get_modified(archive) => Ok(index.clone()),
+        _ => {
+            let index = Arc::new(build_index(archive, kind)?);
+            *slot = Some(index.clone());
+            Ok(index)
+        }
+    }
+}
+
+// Returns (path, is_dir) pairs of the direct children of `inner` in the archive.
+pub fn get_archive_children(archive: &Path, inner: &Path) -> ViewerResult<Vec<(PathBuf, bool)>> {
+    match get_index(archive) {
+        Ok(index) => {
+            let parent = archive.join(inner);
+            let mut dirs = HashSet::new();
+            let mut children = vec![];
+            for entry in index.entries.values() {
+                if let Ok(rest) = entry.name.strip_prefix(inner) {
+                    let mut components = rest.components();
+                    match (components.next(), components.next()) {
+                        (Some(child), None) => children.push((parent.join(child), false)),
+                        (Some(child), Some(_)) => {
+                            if dirs.insert(child.as_os_str().to_os_string()) {
+                                children.push((parent.join(child), true));
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            Ok(children)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub fn has_archive_entry(archive: &Path, inner: &Path) -> bool {
+    match get_index(archive) {
+        Ok(index) => index.entries.contains_key(inner),
+        Err(_) => false,
+    }
+}
+
+pub fn get_archive_entry_size(archive: &Path, inner: &Path) -> ViewerResult<u64> {
+    match get_index(archive) {
+        Ok(index) => match index.entries.get(inner) {
+            Some(entry) => Ok(entry.size),
+            None => Err(ViewerError::NotFound(archive.join(inner))),
+        },
+        Err(err) => Err(err),
+    }
+}
+
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, length: u64) -> ViewerResult<Vec<u8>> {
+    let mut data = Vec::with_capacity(length as usize);
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.take(length).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+pub fn read_archive_entry(
+    archive: &Path,
+    inner: &Path,
+    limit: Option<u64>,
+) -> ViewerResult<Vec<u8>> {
+    let index = get_index(archive)?;
+    let entry = match index.entries.get(inner) {
+        Some(entry) => entry,
+        None => return Err(ViewerError::NotFound(archive.join(inner))),
+    };
+    let length = limit.map_or(entry.size, |limit| limit.min(entry.size));
+
+    match &index.storage {
+        EntryStorage::Zip(zip) => {
+            let mut zip = zip.lock()?;
+            let mut data = Vec::with_capacity(length as usize);
+            let result = match zip.by_index(entry.offset as usize) {
+                Ok(reader) => reader
+                    .take(length)
+                    .read_to_end(&mut data)
+                    .map_err(|err| err.into()),
+                Err(err) => Err(ViewerError::decode(err)),
+            };
+            result.map(|_| data)
+        }
+        EntryStorage::Archive => match File::open(archive) {
+            Ok(mut file) => read_at(&mut file, entry.offset, length),
+            Err(err) => Err(err.into()),
+        },
+        EntryStorage::Spill(spill) => match spill.lock() {
+            Ok(mut file) => read_at(&mut *file, entry.offset, length),
+            Err(err) => Err(err.into()),
+        },
+    }
+}
+
+/*
+ * Test data structure:
+ * test_data/archive/ +- a/ +- a.png
+ *                    |
+ *                    +- b.zip/ +- a.png
+ *                    |         +- b.png
+ *                    |         +- c/ +- a.png
+ *                    |         +- d.txt
+ *                    |
+ *                    +- c/ +- a.png
+ */
+#[test]
+fn test_split_archive_path() {
+    let archive = PathBuf::from("test_data/archive/b.zip");
+
+    assert_eq!(
+        split_archive_path(Path::new("test_data/archive/b.zip/c/a.png")),
+        Some((archive.clone(), PathBuf::from("c/a.png")))
+    );
+    assert_eq!(
+        split_archive_path(Path::new("test_data/archive/b.zip")),
+        Some((archive, PathBuf::new()))
+    );
+    assert_eq!(
+        split_archive_path(Path::new("test_data/archive/a/a.png")),
+        None
+    );
+}
+
+#[test]
+fn test_get_archive_children() {
+    let archive = Path::new("test_data/archive/b.zip");
+
+    let mut actual1 = get_archive_children(archive, Path::new("")).unwrap();
+    actual1.sort();
+    assert_eq!(
+        actual1,
+        vec![
+            (archive.join("a.png"), false),
+            (archive.join("b.png"), false),
+            (archive.join("c"), true),
+            (archive.join("d.txt"), false),
+        ]
+    );
+
+    let actual2 = get_archive_children(archive, Path::new("c")).unwrap();
+    assert_eq!(actual2, vec![(archive.join("c/a.png"), false)]);
+}
+
+#[test]
+fn test_read_archive_entry() {
+    let archive = Path::new("test_data/archive/b.zip");
+    let expected = std::fs::read("test_data/image/a.png").unwrap();
+
+    assert_eq!(
+        read_archive_entry(archive, Path::new("c/a.png"), None),
+        Ok(expected.clone())
+    );
+    assert_eq!(
+        read_archive_entry(archive, Path::new("a.png"), Some(4)),
+        Ok(expected[..4].to_vec())
+    );
+    assert!(read_archive_entry(archive, Path::new("e.png"), None).is_err());
+    assert!(has_archive_entry(archive, Path::new("b.png")));
+    assert_eq!(
+        get_archive_entry_size(archive, Path::new("b.png")),
+        Ok(expected.len() as u64)
+    );
+    assert!(!has_archive_entry(archive, Path::new("c")));
+}
+
+#[test]
+fn test_archive_formats() {
+    let expected = std::fs::read("test_data/image/a.png").unwrap();
+
+    // "dot.tar" is made of "." and names its entries "./a.png"
+    for name in ["b.tar", "b.tar.gz", "b.tar.zst", "b.7z", "dot.tar"] {
+        let archive = Path::new("test_data/archive_formats").join(name);
+        assert!(is_archive(&archive));
+
+        let mut children = get_archive_children(&archive, Path::new("")).unwrap();
+        children.sort();
+        assert_eq!(
+            children,
+            vec![
+                (archive.join("a.png"), false),
+                (archive.join("b.png"), false),
+                (archive.join("c"), true),
+                (archive.join("d.txt"), false),
+            ]
+        );
+
+        assert_eq!(
+            read_archive_entry(&archive, Path::new("c/a.png"), None),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            read_archive_entry(&archive, Path::new("b.png"), Some(4)),
+            Ok(expected[..4].to_vec())
+        );
+        assert!(Arc::ptr_eq(
+            &get_index(&archive).unwrap(),
+            &get_index(&archive).unwrap()
+        ));
+    }
+}
+
+#[test]
+fn test_normalize_entry_name() {
+    let normalize = |name: &str| normalize_entry_name(Path::new(name));
+    assert_eq!(normalize("a/b.png"), Some(PathBuf::from("a/b.png")));
+    assert_eq!(normalize("./a/./b.png"), Some(PathBuf::from("a/b.png")));
+    assert_eq!(normalize("../b.png"), None);
+    assert_eq!(normalize("a/../../b.png"), None);
+    assert_eq!(normalize("/b.png"), None);
+    assert_eq!(normalize("./"), None);
+}
+
+#[test]
+fn test_index_cache() {
+    let mut cache = IndexCache::default();
+    let path = |i: usize| PathBuf::from(format!("{}.zip", i));
+
+    let first = cache.slot(&path(0));
+    assert!(Arc::ptr_eq(&first, &cache.slot(&path(0))));
+    for i in 1..MAX_ARCHIVE_INDICES {
+        cache.slot(&path(i));
+    }
+    // "0.zip" is used more recently than "1.zip"
+    cache.slot(&path(0));
+    cache.slot(&path(MAX_ARCHIVE_INDICES));
+    assert_eq!(cache.slots.len(), MAX_ARCHIVE_INDICES);
+    assert!(cache.slots.contains_key(&path(0)));
+    assert!(!cache.slots.contains_key(&path(1)));
+}
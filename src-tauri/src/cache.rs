@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use mime;
+
+struct CacheEntry {
+    mimetype: mime::Mime,
+    data: Arc<Vec<u8>>,
+    last_used: u64,
+}
+
+// Least-recently-used cache of image payloads bounded by total bytes.
+pub struct ImageCache {
+    entries: HashMap<PathBuf, CacheEntry>,
+    budget: usize,
+    used: usize,
+    clock: u64,
+}
+
+impl ImageCache {
+    pub fn new(budget: usize) -> Self {
+        log::info!("Image cache budget: {:?} bytes", budget);
+        Self {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn contains(&self, path: &PathBuf) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    pub fn get(&mut self, path: &PathBuf) -> Option<(mime::Mime, Arc<Vec<u8>>)> {
+        self.clock += 1;
+        match self.entries.get_mut(path) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                Some((entry.mimetype.clone(), entry.data.clone()))
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, path: &PathBuf, mimetype: &mime::Mime, data: Arc<Vec<u8>>) {
+        if data.len() > self.budget {
+            log::debug!("{:?} is too large to be cached", path);
+            return;
+        }
+
+        self.clock += 1;
+        self.used += data.len();
+        let entry = CacheEntry {
+            mimetype: mimetype.clone(),
+            data,
+            last_used: self.clock,
+        };
+        if let Some(old) = self.entries.insert(path.clone(), entry) {
+            self.used -= old.data.len();
+        }
+        self.evict();
+    }
+
+    pub fn remove(&mut self, path: &PathBuf) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.used -= entry.data.len();
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.budget {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone());
+            match oldest.and_then(|path| self.entries.remove(&path)) {
+                Some(entry) => self.used -= entry.data.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+#[test]
+fn test_image_cache() {
+    let mut cache = ImageCache::new(10);
+    let path = |name: &str| PathBuf::from(name);
+
+    cache.insert(&path("a"), &mime::IMAGE_PNG, Arc::new(vec![0; 4]));
+    cache.insert(&path("b"), &mime::IMAGE_PNG, Arc::new(vec![0; 4]));
+    assert!(cache.contains(&path("a")));
+    assert!(cache.contains(&path("b")));
+
+    // "a" is used more recently than "b"
+    assert!(cache.get(&path("a")).is_some());
+    cache.insert(&path("c"), &mime::IMAGE_PNG, Arc::new(vec![0; 4]));
+    assert!(cache.contains(&path("a")));
+    assert!(!cache.contains(&path("b")));
+    assert!(cache.contains(&path("c")));
+
+    // Entries larger than the budget are not cached
+    cache.insert(&path("d"), &mime::IMAGE_PNG, Arc::new(vec![0; 11]));
+    assert!(!cache.contains(&path("d")));
+    assert!(cache.get(&path("d")).is_none());
+
+    cache.remove(&path("a"));
+    assert!(!cache.contains(&path("a")));
+    cache.insert(&path("e"), &mime::IMAGE_PNG, Arc::new(vec![0; 6]));
+    assert!(cache.contains(&path("c")));
+}
@@ -1,48 +1,624 @@
+use std::env::current_dir;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use ipc::{
+    Args, ChangeSort, ChangeSpread, DirectoryListing, GetView, ImageSource, Jump, JumpTarget,
+    ListDirectory, NextDirectory, NextImage, OpenPaths, PrevDirectory, PrevImage, Response,
+    SaveOrientation, SetView, Transform,
+};
+use mime;
 use tauri::State;
 
+use super::error::{ViewerError, ViewerResult};
+use super::exif_data::{get_orientation, set_orientation};
+use super::image::create_source;
+use super::sort::apply_sort_change;
+use super::state::ViewerState;
 use super::utils::{get_next_image, get_prev_image};
-use super::ViewerStateManager;
+use super::{
+    PrefetcherManager, RequestManager, SessionManager, SourceRegistryManager, ViewerStateManager,
+};
 
-#[tauri::command]
-pub fn next_image(state_manager: State<ViewerStateManager>, moves: &str) -> Result<String, String> {
-    match state_manager.0.lock() {
-        Ok(mut state) => match moves.parse::<usize>() {
-            Ok(n_moves) => get_next_image(&mut state, n_moves as i32),
-            Err(err) => Err(format!("{:?}", err)),
+// Stale moves of the latest request are replayed at most this many times
+const MAX_REPLAYS: usize = 2;
+
+// Moves a snapshot of the state outside of the lock, so that directory walks and
+// file detection do not block other requests. The snapshot is committed only when
+// no other request has changed the state in the meantime; otherwise it is discarded,
+// and the move is replayed on the latest state unless a newer request has been sent.
+async fn navigate<F>(
+    state_manager: &ViewerStateManager,
+    request_manager: &RequestManager,
+    request_id: u32,
+    navigator: F,
+) -> ViewerResult<(ViewerState, PathBuf, mime::Mime)>
+where
+    F: Fn(&mut ViewerState) -> ViewerResult<(PathBuf, mime::Mime)> + Send + Sync + 'static,
+{
+    let navigator = Arc::new(navigator);
+    for _ in 0..=MAX_REPLAYS {
+        // Outdated requests are not moved at all, as their responses are discarded
+        if is_superseded(request_manager, request_id) {
+            return Err(ViewerError::Superseded(request_id));
+        }
+
+        let (mut snapshot, generation) = match state_manager.0.lock() {
+            Ok(state) => (state.value.clone(), state.generation),
+            Err(err) => return Err(err.into()),
+        };
+
+        let view = snapshot.view();
+        let move_snapshot = navigator.clone();
+        let (mut snapshot, result) = match tauri::async_runtime::spawn_blocking(move || {
+            // Only wrapping around in this move is reported
+            snapshot.clear_wrapped();
+            let result = move_snapshot(&mut snapshot);
+            (snapshot, result)
+        })
+        .await
+        {
+            Ok(moved) => moved,
+            Err(err) => return Err(ViewerError::internal(err)),
+        };
+
+        match state_manager.0.lock() {
+            Ok(mut state) if state.generation == generation => {
+                return match result {
+                    Ok((path, mimetype)) => {
+                        // The view is saved without moving, so the latest one is kept unless
+                        // the move has restored another
+                        if snapshot.view() == view {
+                            snapshot.set_view(state.value.view());
+                        }
+                        state.value = snapshot.clone();
+                        state.generation += 1;
+                        Ok((snapshot, path, mimetype))
+                    }
+                    Err(err) => Err(err),
+                };
+            }
+            Ok(_) => log::debug!("Discard stale state (generation: {:?})", generation),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Err(ViewerError::Superseded(request_id))
+}
+
+fn begin_request(request_manager: &RequestManager, request_id: u32) {
+    match request_id {
+        // The webview has been reloaded, so the ids of the former page are forgotten
+        ipc::FIRST_REQUEST_ID => request_manager.0.store(request_id, Ordering::SeqCst),
+        _ => {
+            request_manager.0.fetch_max(request_id, Ordering::SeqCst);
+        }
+    }
+}
+
+fn is_superseded(request_manager: &RequestManager, request_id: u32) -> bool {
+    request_manager.0.load(Ordering::SeqCst) > request_id
+}
+
+async fn respond(
+    result: ViewerResult<(ViewerState, PathBuf, mime::Mime)>,
+    request_id: u32,
+    request_manager: &RequestManager,
+    registry_manager: &SourceRegistryManager,
+    prefetcher_manager: &PrefetcherManager,
+) -> ViewerResult<ImageSource> {
+    let (state, path, mimetype) = match result {
+        // The cursor has been moved, but the payload is not prepared for outdated requests
+        Ok(_) if is_superseded(request_manager, request_id) => {
+            return Err(ViewerError::Superseded(request_id))
+        }
+        Ok(moved) => moved,
+        Err(err) => return Err(err),
+    };
+    let facing = match state.facing() {
+        Some(facing) => match state.detector().detect(&facing) {
+            Ok(facing_mimetype) => Some((facing, facing_mimetype)),
+            Err(err) => {
+                log::info!("{:?}", err);
+                None
+            }
         },
-        Err(err) => Err(format!("{:?}", err)),
+        None => None,
+    };
+    let (token, facing) = match registry_manager.0.lock() {
+        Ok(mut registry) => (
+            registry.register(&path, &mimetype),
+            facing.map(|(facing, facing_mimetype)| {
+                let token = registry.register(&facing, &facing_mimetype);
+                (token, facing, facing_mimetype)
+            }),
+        ),
+        Err(err) => return Err(err.into()),
+    };
+    prefetcher_manager.0.request(&state);
+
+    // Reading the metadata touches the file, so it is kept out of the async runtime
+    match tauri::async_runtime::spawn_blocking(move || ImageSource {
+        sort: state.sort(),
+        wrapped: state.wrapped(),
+        position: state.position(),
+        total: state.total(),
+        spread: state.spread(),
+        facing: facing.map(|(token, facing, facing_mimetype)| {
+            let index = state.cursor() + 1;
+            Box::new(create_source(
+                token,
+                &facing,
+                &facing_mimetype,
+                index,
+                state.count(),
+            ))
+        }),
+        ..create_source(token, &path, &mimetype, state.cursor(), state.count())
+    })
+    .await
+    {
+        Ok(source) => Ok(source),
+        Err(err) => Err(ViewerError::internal(err)),
     }
 }
 
 #[tauri::command]
-pub fn prev_image(state_manager: State<ViewerStateManager>, moves: &str) -> Result<String, String> {
+pub async fn next_image(
+    state_manager: State<'_, ViewerStateManager>,
+    registry_manager: State<'_, SourceRegistryManager>,
+    prefetcher_manager: State<'_, PrefetcherManager>,
+    request_manager: State<'_, RequestManager>,
+    args: Args<NextImage>,
+) -> Response<NextImage> {
+    begin_request(&request_manager, args.request_id);
+    respond(
+        navigate(
+            &state_manager,
+            &request_manager,
+            args.request_id,
+            move |state| get_next_image(state, args.moves),
+        )
+        .await,
+        args.request_id,
+        &request_manager,
+        &registry_manager,
+        &prefetcher_manager,
+    )
+    .await
+    .map_err(ipc::Error::from)
+}
+
+#[tauri::command]
+pub async fn prev_image(
+    state_manager: State<'_, ViewerStateManager>,
+    registry_manager: State<'_, SourceRegistryManager>,
+    prefetcher_manager: State<'_, PrefetcherManager>,
+    request_manager: State<'_, RequestManager>,
+    args: Args<PrevImage>,
+) -> Response<PrevImage> {
+    begin_request(&request_manager, args.request_id);
+    respond(
+        navigate(
+            &state_manager,
+            &request_manager,
+            args.request_id,
+            move |state| get_prev_image(state, args.moves),
+        )
+        .await,
+        args.request_id,
+        &request_manager,
+        &registry_manager,
+        &prefetcher_manager,
+    )
+    .await
+    .map_err(ipc::Error::from)
+}
+
+#[tauri::command]
+pub async fn next_directory(
+    state_manager: State<'_, ViewerStateManager>,
+    registry_manager: State<'_, SourceRegistryManager>,
+    prefetcher_manager: State<'_, PrefetcherManager>,
+    request_manager: State<'_, RequestManager>,
+    args: Args<NextDirectory>,
+) -> Response<NextDirectory> {
+    begin_request(&request_manager, args.request_id);
+    respond(
+        navigate(
+            &state_manager,
+            &request_manager,
+            args.request_id,
+            |state| match state.next_directory() {
+                Ok(_) => get_next_image(state, 0),
+                Err(err) => Err(err),
+            },
+        )
+        .await,
+        args.request_id,
+        &request_manager,
+        &registry_manager,
+        &prefetcher_manager,
+    )
+    .await
+    .map_err(ipc::Error::from)
+}
+
+#[tauri::command]
+pub async fn prev_directory(
+    state_manager: State<'_, ViewerStateManager>,
+    registry_manager: State<'_, SourceRegistryManager>,
+    prefetcher_manager: State<'_, PrefetcherManager>,
+    request_manager: State<'_, RequestManager>,
+    args: Args<PrevDirectory>,
+) -> Response<PrevDirectory> {
+    begin_request(&request_manager, args.request_id);
+    respond(
+        navigate(
+            &state_manager,
+            &request_manager,
+            args.request_id,
+            |state| match state.prev_directory() {
+                Ok(_) => get_prev_image(state, 0),
+                Err(err) => Err(err),
+            },
+        )
+        .await,
+        args.request_id,
+        &request_manager,
+        &registry_manager,
+        &prefetcher_manager,
+    )
+    .await
+    .map_err(ipc::Error::from)
+}
+
+#[tauri::command]
+pub async fn change_sort(
+    state_manager: State<'_, ViewerStateManager>,
+    registry_manager: State<'_, SourceRegistryManager>,
+    prefetcher_manager: State<'_, PrefetcherManager>,
+    request_manager: State<'_, RequestManager>,
+    args: Args<ChangeSort>,
+) -> Response<ChangeSort> {
+    begin_request(&request_manager, args.request_id);
+    let (target, change) = (args.target, args.change);
+    respond(
+        navigate(
+            &state_manager,
+            &request_manager,
+            args.request_id,
+            move |state| {
+                state.set_sort(apply_sort_change(state.sort(), target, change));
+                match state.reload_files() {
+                    Ok(_) => get_next_image(state, 0),
+                    Err(err) => Err(err),
+                }
+            },
+        )
+        .await,
+        args.request_id,
+        &request_manager,
+        &registry_manager,
+        &prefetcher_manager,
+    )
+    .await
+    .map_err(ipc::Error::from)
+}
+
+#[tauri::command]
+pub async fn change_spread(
+    state_manager: State<'_, ViewerStateManager>,
+    registry_manager: State<'_, SourceRegistryManager>,
+    prefetcher_manager: State<'_, PrefetcherManager>,
+    request_manager: State<'_, RequestManager>,
+    args: Args<ChangeSpread>,
+) -> Response<ChangeSpread> {
+    begin_request(&request_manager, args.request_id);
+    let change = args.change;
+    respond(
+        navigate(
+            &state_manager,
+            &request_manager,
+            args.request_id,
+            move |state| {
+                state.set_spread(state.spread().apply(change));
+                get_next_image(state, 0)
+            },
+        )
+        .await,
+        args.request_id,
+        &request_manager,
+        &registry_manager,
+        &prefetcher_manager,
+    )
+    .await
+    .map_err(ipc::Error::from)
+}
+
+#[tauri::command]
+pub async fn jump(
+    state_manager: State<'_, ViewerStateManager>,
+    registry_manager: State<'_, SourceRegistryManager>,
+    prefetcher_manager: State<'_, PrefetcherManager>,
+    request_manager: State<'_, RequestManager>,
+    args: Args<Jump>,
+) -> Response<Jump> {
+    begin_request(&request_manager, args.request_id);
+    let target = args.target;
+    respond(
+        navigate(
+            &state_manager,
+            &request_manager,
+            args.request_id,
+            move |state| {
+                // Images which fail to be detected are skipped away from the ends
+                let (result, forward) = match target {
+                    JumpTarget::First => {
+                        state.move_first();
+                        (Ok(()), true)
+                    }
+                    JumpTarget::Last => {
+                        state.move_last();
+                        (Ok(()), false)
+                    }
+                    JumpTarget::FirstInCollection => (state.move_collection_first(), true),
+                    JumpTarget::LastInCollection => (state.move_collection_last(), false),
+                    JumpTarget::Position(position) => (state.jump_to(position), true),
+                    JumpTarget::Percent(percent) => (state.seek(percent), true),
+                };
+                match (result, forward) {
+                    (Ok(_), true) => get_next_image(state, 0),
+                    (Ok(_), false) => get_prev_image(state, 0),
+                    (Err(err), _) => Err(err),
+                }
+            },
+        )
+        .await,
+        args.request_id,
+        &request_manager,
+        &registry_manager,
+        &prefetcher_manager,
+    )
+    .await
+    .map_err(ipc::Error::from)
+}
+
+#[tauri::command]
+pub async fn open_paths(
+    state_manager: State<'_, ViewerStateManager>,
+    registry_manager: State<'_, SourceRegistryManager>,
+    prefetcher_manager: State<'_, PrefetcherManager>,
+    request_manager: State<'_, RequestManager>,
+    session_manager: State<'_, SessionManager>,
+    args: Args<OpenPaths>,
+) -> Response<OpenPaths> {
+    begin_request(&request_manager, args.request_id);
+    // The position in the collection being closed is kept for the next time
+    let session = match (session_manager.0.lock(), state_manager.0.lock()) {
+        (Ok(mut session), Ok(state)) => {
+            session.record(&state.value);
+            session.clone()
+        }
+        (Err(err), _) => return Err(ViewerError::from(err).into()),
+        (_, Err(err)) => return Err(ViewerError::from(err).into()),
+    };
+    let (paths, restore) = (args.paths, session_manager.1);
+    respond(
+        navigate(
+            &state_manager,
+            &request_manager,
+            args.request_id,
+            move |state| {
+                *state = state.reopen(&paths);
+                if restore {
+                    session.restore(state);
+                }
+                match state.reload_files() {
+                    Ok(_) => get_next_image(state, 0),
+                    Err(err) => Err(err),
+                }
+            },
+        )
+        .await,
+        args.request_id,
+        &request_manager,
+        &registry_manager,
+        &prefetcher_manager,
+    )
+    .await
+    .map_err(ipc::Error::from)
+}
+
+#[tauri::command]
+pub async fn list_directory(
+    state_manager: State<'_, ViewerStateManager>,
+    args: Args<ListDirectory>,
+) -> Response<ListDirectory> {
+    let state = match state_manager.0.lock() {
+        Ok(state) => state.value.clone(),
+        Err(err) => return Err(ViewerError::from(err).into()),
+    };
+    let path = match args.path.or_else(tauri::api::path::home_dir) {
+        Some(path) => path,
+        None => current_dir().unwrap_or_default(),
+    };
+
+    // Image files may be detected by reading them
+    match tauri::async_runtime::spawn_blocking(move || match state.list_directory(&path) {
+        Ok((directories, files)) => Ok(DirectoryListing {
+            parent: path.parent().map(Path::to_path_buf),
+            path,
+            directories,
+            files,
+        }),
+        Err(err) => Err(err),
+    })
+    .await
+    {
+        Ok(listing) => listing,
+        Err(err) => Err(ViewerError::internal(err)),
+    }
+    .map_err(ipc::Error::from)
+}
+
+#[tauri::command]
+pub fn get_view(state_manager: State<'_, ViewerStateManager>) -> Response<GetView> {
     match state_manager.0.lock() {
-        Ok(mut state) => match moves.parse::<usize>() {
-            Ok(n_moves) => get_prev_image(&mut state, -(n_moves as i32)),
-            Err(err) => Err(format!("{:?}", err)),
-        },
-        Err(err) => Err(format!("{:?}", err)),
+        Ok(state) => Ok(state.value.view()),
+        Err(err) => Err(ViewerError::from(err).into()),
     }
 }
 
 #[tauri::command]
-pub fn next_directory(state_manager: State<ViewerStateManager>) -> Result<String, String> {
+pub fn set_view(
+    state_manager: State<'_, ViewerStateManager>,
+    args: Args<SetView>,
+) -> Response<SetView> {
     match state_manager.0.lock() {
-        Ok(mut state) => match state.next_directory() {
-            Ok(_) => get_next_image(&mut state, 0),
-            Err(err) => Err(format!("{:?}", err)),
-        },
-        Err(err) => Err(format!("{:?}", err)),
+        Ok(mut state) => {
+            // Pending moves keep the view when they are committed
+            state.value.set_view(args);
+            Ok(())
+        }
+        Err(err) => Err(ViewerError::from(err).into()),
     }
 }
 
 #[tauri::command]
-pub fn prev_directory(state_manager: State<ViewerStateManager>) -> Result<String, String> {
+pub async fn save_orientation(
+    state_manager: State<'_, ViewerStateManager>,
+    prefetcher_manager: State<'_, PrefetcherManager>,
+    args: Args<SaveOrientation>,
+) -> Response<SaveOrientation> {
+    let (path, transform) = (args.path, args.transform);
+    // Only the images being shown are rewritten
     match state_manager.0.lock() {
-        Ok(mut state) => match state.prev_directory() {
-            Ok(_) => get_prev_image(&mut state, 0),
-            Err(err) => Err(format!("{:?}", err)),
-        },
-        Err(err) => Err(format!("{:?}", err)),
+        Ok(state) if state.value.get().as_ref() == Ok(&path) => (),
+        Ok(state) if state.value.facing().as_ref() == Some(&path) => (),
+        Ok(_) => {
+            let message = format!("Not shown: {:?}", path);
+            return Err(ViewerError::InvalidArgument(message).into());
+        }
+        Err(err) => return Err(ViewerError::from(err).into()),
     }
+    let saved_path = path.clone();
+    match tauri::async_runtime::spawn_blocking(move || {
+        let current = Transform::from_orientation(get_orientation(&path));
+        set_orientation(&path, current.then(transform).orientation())
+    })
+    .await
+    {
+        Ok(Ok(_)) => {
+            prefetcher_manager.0.invalidate(&saved_path);
+            // Turning the image may change whether it is shown alone
+            match state_manager.0.lock() {
+                Ok(state) => state.value.forget_page(&saved_path),
+                Err(err) => log::warn!("{:?}", err),
+            }
+            Ok(())
+        }
+        Ok(Err(err)) => Err(err),
+        Err(err) => Err(ViewerError::internal(err)),
+    }
+    .map_err(ipc::Error::from)
+}
+
+#[test]
+fn test_navigate_stale() {
+    use super::utils::Versioned;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicU32, AtomicUsize};
+    use std::sync::Mutex;
+
+    let extensions = HashSet::from([String::from("png")]);
+    let state = ViewerState::new(&["test_data/spread/a.png"], extensions);
+    // Shared with the moves, which run on other threads
+    let state_manager: &'static ViewerStateManager = Box::leak(Box::new(ViewerStateManager(
+        Mutex::new(Versioned::new(state)),
+    )));
+    let request_manager: &'static RequestManager =
+        Box::leak(Box::new(RequestManager(AtomicU32::new(0))));
+    let calls: &'static AtomicUsize = Box::leak(Box::new(AtomicUsize::new(0)));
+    let generation = || state_manager.0.lock().unwrap().generation;
+
+    begin_request(request_manager, 1);
+    let result =
+        tauri::async_runtime::block_on(navigate(state_manager, request_manager, 1, |state| {
+            match state.reload_files() {
+                Ok(_) => get_next_image(state, 0),
+                Err(err) => Err(err),
+            }
+        }));
+    assert!(result.is_ok());
+    assert_eq!(generation(), 1);
+
+    // Outdated requests are not moved
+    begin_request(request_manager, 3);
+    let result =
+        tauri::async_runtime::block_on(navigate(state_manager, request_manager, 2, |state| {
+            get_next_image(state, 1)
+        }));
+    assert_eq!(result.err(), Some(ViewerError::Superseded(2)));
+    assert_eq!(generation(), 1);
+
+    // The latest request is replayed a limited number of times
+    let result =
+        tauri::async_runtime::block_on(navigate(state_manager, request_manager, 3, move |state| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            state_manager.0.lock().unwrap().generation += 1;
+            get_next_image(state, 1)
+        }));
+    assert_eq!(result.err(), Some(ViewerError::Superseded(3)));
+    assert_eq!(calls.load(Ordering::SeqCst), MAX_REPLAYS + 1);
+
+    // Stale moves of outdated requests are not replayed
+    calls.store(0, Ordering::SeqCst);
+    let result =
+        tauri::async_runtime::block_on(navigate(state_manager, request_manager, 4, move |state| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            begin_request(request_manager, 5);
+            state_manager.0.lock().unwrap().generation += 1;
+            get_next_image(state, 1)
+        }));
+    assert_eq!(result.err(), Some(ViewerError::Superseded(4)));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        state_manager.0.lock().unwrap().value.get(),
+        Ok(std::path::PathBuf::from("test_data/spread/a.png"))
+    );
+
+    // Views saved during a move are not reverted
+    begin_request(request_manager, 6);
+    let last = generation();
+    let result =
+        tauri::async_runtime::block_on(navigate(state_manager, request_manager, 6, move |state| {
+            let view = ipc::ViewSettings {
+                zoom: 2.0,
+                ..Default::default()
+            };
+            state_manager.0.lock().unwrap().value.set_view(view);
+            get_next_image(state, 1)
+        }));
+    assert!(result.is_ok());
+    assert_eq!(generation(), last + 1);
+    let state = state_manager.0.lock().unwrap();
+    assert_eq!(state.value.view().zoom, 2.0);
+}
+
+#[test]
+fn test_begin_request() {
+    use std::sync::atomic::AtomicU32;
+
+    let request_manager = RequestManager(AtomicU32::new(0));
+    begin_request(&request_manager, 5);
+    begin_request(&request_manager, 3);
+    assert!(is_superseded(&request_manager, 4));
+    assert!(!is_superseded(&request_manager, 5));
+
+    // A reloaded webview starts over
+    begin_request(&request_manager, ipc::FIRST_REQUEST_ID);
+    assert!(!is_superseded(&request_manager, ipc::FIRST_REQUEST_ID));
+    begin_request(&request_manager, ipc::FIRST_REQUEST_ID + 1);
+    assert!(is_superseded(&request_manager, ipc::FIRST_REQUEST_ID));
 }
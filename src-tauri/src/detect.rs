@@ -0,0 +1,229 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use mime;
+
+use super::error::{ViewerError, ViewerResult};
+use super::image::get_mimetype;
+use super::path::read_file;
+
+const SNIFF_LENGTH: usize = 22;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DetectPolicy {
+    // Trust file extensions only
+    Extension,
+    // Ignore file extensions and trust file contents only
+    Sniff,
+    // Filter by file extensions, then determine the format from file contents
+    Verify,
+}
+
+impl Default for DetectPolicy {
+    fn default() -> Self {
+        DetectPolicy::Verify
+    }
+}
+
+impl FromStr for DetectPolicy {
+    type Err = ViewerError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "extension" => Ok(DetectPolicy::Extension),
+            "sniff" => Ok(DetectPolicy::Sniff),
+            "verify" => Ok(DetectPolicy::Verify),
+            _ => Err(ViewerError::InvalidArgument(format!(
+                "Unknown detect policy: {:?}",
+                name
+            ))),
+        }
+    }
+}
+
+// ICO has only 4 magic bytes, so the first directory entry is checked as well
+fn is_icon(header: &[u8]) -> bool {
+    let read_u16 = |offset: usize| match header.get(offset..offset + 2) {
+        Some(bytes) => Some(u16::from_le_bytes([bytes[0], bytes[1]])),
+        None => None,
+    };
+    let read_u32 = |offset: usize| match header.get(offset..offset + 4) {
+        Some(bytes) => Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => None,
+    };
+    match (
+        header.get(0..4),
+        read_u16(4),
+        header.get(9),
+        read_u16(10),
+        read_u32(14),
+        read_u32(18),
+    ) {
+        // Number of images, then reserved byte, color planes, data size and data offset
+        (Some([0, 0, 1, 0]), Some(count), Some(0), Some(planes), Some(size), Some(offset)) => {
+            count > 0 && planes <= 1 && size > 0 && offset as usize >= 6 + 16 * count as usize
+        }
+        _ => false,
+    }
+}
+
+pub fn sniff_mimetype(header: &[u8]) -> Option<mime::Mime> {
+    let mimetype = match header {
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => "image/png",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => "image/gif",
+        [b'B', b'M', _, _, _, _, 0, 0, 0, 0, ..] => "image/bmp",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f' | b's', ..] => "image/avif",
+        [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => "image/tiff",
+        header if is_icon(header) => "image/x-icon",
+        [b'q', b'o', b'i', b'f', ..] => "image/qoi",
+        [b'P', b'1'..=b'6', b' ' | b'\t' | b'\n' | b'\r', ..] => "image/x-portable-anymap",
+        _ => return None,
+    };
+    Some(mimetype.parse().unwrap())
+}
+
+fn sniff_file(path: &PathBuf) -> ViewerResult<Option<mime::Mime>> {
+    read_file(path, Some(SNIFF_LENGTH as u64)).map(|header| sniff_mimetype(&header))
+}
+
+// Some formats (e.g. TGA) have no magic bytes, so only their extension can be used.
+fn detect_without_magic(path: &PathBuf) -> ViewerResult<mime::Mime> {
+    match get_mimetype(path) {
+        Ok(mimetype) if mimetype.essence_str() == "image/x-tga" => Ok(mimetype),
+        _ => Err(ViewerError::UnsupportedFormat(path.clone())),
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct ImageDetector {
+    policy: DetectPolicy,
+    extensions: HashSet<String>,
+}
+
+impl ImageDetector {
+    pub fn new(policy: DetectPolicy, extensions: HashSet<String>) -> Self {
+        Self { policy, extensions }
+    }
+
+    pub fn policy(&self) -> DetectPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: DetectPolicy) {
+        self.policy = policy;
+    }
+
+    fn has_extension(&self, path: &PathBuf) -> bool {
+        match path.extension() {
+            Some(ext) => match ext.to_str() {
+                Some(extension) => self.extensions.contains(&extension.to_ascii_lowercase()),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    pub fn detect(&self, path: &PathBuf) -> ViewerResult<mime::Mime> {
+        match self.policy {
+            DetectPolicy::Extension if self.has_extension(path) => get_mimetype(path),
+            DetectPolicy::Verify if self.has_extension(path) => match sniff_file(path) {
+                Ok(Some(mimetype)) => Ok(mimetype),
+                Ok(None) => detect_without_magic(path),
+                Err(err) => Err(err),
+            },
+            DetectPolicy::Sniff => match sniff_file(path) {
+                Ok(Some(mimetype)) => Ok(mimetype),
+                Ok(None) => detect_without_magic(path),
+                Err(err) => Err(err),
+            },
+            _ => Err(ViewerError::UnsupportedFormat(path.clone())),
+        }
+    }
+
+    pub fn is_image(&self, path: &PathBuf) -> bool {
+        match self.policy {
+            DetectPolicy::Extension => self.has_extension(path),
+            _ => self.detect(path).is_ok(),
+        }
+    }
+}
+
+#[test]
+fn test_sniff_mimetype() {
+    let sniff = |header: &[u8]| sniff_mimetype(header).map(|m| m.to_string());
+
+    assert_eq!(
+        sniff(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0x00]),
+        Some("image/png".to_string())
+    );
+    assert_eq!(
+        sniff(&[0xFF, 0xD8, 0xFF, 0xE0]),
+        Some("image/jpeg".to_string())
+    );
+    assert_eq!(sniff(b"GIF89a"), Some("image/gif".to_string()));
+    assert_eq!(
+        sniff(b"RIFF\x00\x00\x00\x00WEBPVP8 "),
+        Some("image/webp".to_string())
+    );
+    assert_eq!(
+        sniff(b"\x00\x00\x00\x1cftypavif"),
+        Some("image/avif".to_string())
+    );
+    assert_eq!(
+        sniff(b"\x00\x00\x00\x1cftypavis"),
+        Some("image/avif".to_string())
+    );
+    assert_eq!(
+        sniff(b"P6\n2 2\n255\n"),
+        Some("image/x-portable-anymap".to_string())
+    );
+    assert_eq!(sniff(b"qoif"), Some("image/qoi".to_string()));
+    assert_eq!(
+        sniff(b"\0\0\x01\0\x01\0\x10\x10\0\0\x01\0\x20\0\x68\x04\0\0\x16\0\0\0"),
+        Some("image/x-icon".to_string())
+    );
+    // Other files starting with the same bytes
+    assert_eq!(
+        sniff(b"\0\0\x01\0\0\0\x10\x10\0\0\x01\0\x20\0\x68\x04\0\0\x16\0\0\0"),
+        None
+    );
+    assert_eq!(
+        sniff(b"\0\0\x01\0\x01\0\x10\x10\0\0\x01\0\x20\0\x68\x04\0\0\x02\0\0\0"),
+        None
+    );
+    assert_eq!(sniff(b"\0\0\x01\0\x01\0"), None);
+    assert_eq!(sniff(b"BMW is a car"), None);
+    assert_eq!(sniff(b"plain text"), None);
+    assert_eq!(sniff(b""), None);
+}
+
+#[test]
+fn test_image_detector() {
+    let extensions = HashSet::from(["png".to_string(), "jpg".to_string(), "tga".to_string()]);
+    let png = PathBuf::from("test_data/image/a.png");
+    let mislabelled = PathBuf::from("test_data/image/png.jpg");
+    let no_extension = PathBuf::from("test_data/image/png");
+    let tga = PathBuf::from("test_data/image/a.tga");
+    let text = PathBuf::from("test_data/image/text.jpg");
+
+    let detector1 = ImageDetector::new(DetectPolicy::Extension, extensions.clone());
+    assert_eq!(detector1.detect(&png), Ok(mime::IMAGE_PNG));
+    assert_eq!(detector1.detect(&mislabelled), Ok(mime::IMAGE_JPEG));
+    assert!(!detector1.is_image(&no_extension));
+    assert!(detector1.is_image(&text));
+
+    let detector2 = ImageDetector::new(DetectPolicy::Sniff, extensions.clone());
+    assert_eq!(detector2.detect(&mislabelled), Ok(mime::IMAGE_PNG));
+    assert_eq!(detector2.detect(&no_extension), Ok(mime::IMAGE_PNG));
+    assert!(detector2.is_image(&tga));
+    assert!(!detector2.is_image(&text));
+
+    let detector3 = ImageDetector::new(DetectPolicy::Verify, extensions);
+    assert_eq!(detector3.detect(&mislabelled), Ok(mime::IMAGE_PNG));
+    assert!(!detector3.is_image(&no_extension));
+    assert!(detector3.is_image(&tga));
+    assert!(!detector3.is_image(&text));
+}
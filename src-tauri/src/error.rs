@@ -0,0 +1,112 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::PoisonError;
+
+use ipc::ErrorKind;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViewerError {
+    Io(String),
+    NotFound(PathBuf),
+    UnsupportedFormat(PathBuf),
+    Decode(String),
+    NoMoreImages,
+    RootReached(PathBuf),
+    EndOfCollection(PathBuf),
+    NothingOpened,
+    Indexing(PathBuf),
+    PoisonedLock,
+    Superseded(u32),
+    InvalidArgument(String),
+    Internal(String),
+}
+
+pub type ViewerResult<T> = Result<T, ViewerError>;
+
+impl ViewerError {
+    pub fn decode<E: fmt::Debug>(err: E) -> Self {
+        ViewerError::Decode(format!("{:?}", err))
+    }
+
+    pub fn internal<E: fmt::Debug>(err: E) -> Self {
+        ViewerError::Internal(format!("{:?}", err))
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ViewerError::Io(_) => ErrorKind::Io,
+            ViewerError::NotFound(_) => ErrorKind::NotFound,
+            ViewerError::UnsupportedFormat(_) => ErrorKind::UnsupportedFormat,
+            ViewerError::Decode(_) => ErrorKind::Decode,
+            ViewerError::NoMoreImages => ErrorKind::NoMoreImages,
+            ViewerError::RootReached(_) => ErrorKind::RootReached,
+            ViewerError::EndOfCollection(_) => ErrorKind::EndOfCollection,
+            ViewerError::NothingOpened => ErrorKind::NothingOpened,
+            ViewerError::Indexing(_) => ErrorKind::Indexing,
+            ViewerError::PoisonedLock => ErrorKind::PoisonedLock,
+            ViewerError::Superseded(_) => ErrorKind::Superseded,
+            ViewerError::InvalidArgument(_) => ErrorKind::InvalidArgument,
+            ViewerError::Internal(_) => ErrorKind::Internal,
+        }
+    }
+}
+
+impl fmt::Display for ViewerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ViewerError::Io(message) => write!(f, "I/O error: {}", message),
+            ViewerError::NotFound(path) => write!(f, "File is not found: {:?}", path),
+            ViewerError::UnsupportedFormat(path) => write!(f, "Unsupported file: {:?}", path),
+            ViewerError::Decode(message) => write!(f, "Failed to decode: {}", message),
+            ViewerError::NoMoreImages => write!(f, "No more images"),
+            ViewerError::RootReached(path) => write!(f, "Reached the end of {:?}", path),
+            ViewerError::EndOfCollection(root) => {
+                write!(f, "Reached the end of the collection in {:?}", root)
+            }
+            ViewerError::NothingOpened => write!(f, "Nothing is opened"),
+            ViewerError::Indexing(root) => write!(f, "Still indexing images in {:?}", root),
+            ViewerError::PoisonedLock => write!(f, "Lock is poisoned"),
+            ViewerError::Superseded(request_id) => {
+                write!(f, "Request {:?} is superseded", request_id)
+            }
+            ViewerError::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+            ViewerError::Internal(message) => write!(f, "Internal error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ViewerError {}
+
+impl From<std::io::Error> for ViewerError {
+    fn from(err: std::io::Error) -> Self {
+        ViewerError::Io(err.to_string())
+    }
+}
+
+impl<T> From<PoisonError<T>> for ViewerError {
+    fn from(_: PoisonError<T>) -> Self {
+        ViewerError::PoisonedLock
+    }
+}
+
+impl From<ViewerError> for ipc::Error {
+    fn from(err: ViewerError) -> Self {
+        ipc::Error {
+            kind: err.kind(),
+            message: err.to_string(),
+        }
+    }
+}
+
+#[test]
+fn test_viewer_error() {
+    let err = ViewerError::from(std::io::Error::new(
+        std::io::ErrorKind::PermissionDenied,
+        "denied",
+    ));
+    assert_eq!(err.kind(), ErrorKind::Io);
+
+    let ipc_err = ipc::Error::from(ViewerError::NoMoreImages);
+    assert_eq!(ipc_err.kind, ErrorKind::NoMoreImages);
+    assert_eq!(ipc_err.message, "No more images".to_string());
+}
@@ -0,0 +1,257 @@
+use std::fs::{canonicalize, metadata, read, File};
+use std::io::{BufReader, Cursor, Write};
+use std::path::Path;
+
+use exif::{Exif, In, Reader, Tag, Value};
+use tempfile::NamedTempFile;
+
+use super::archive::split_archive_path;
+use super::error::{ViewerError, ViewerResult};
+use super::path::read_file;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const ORIENTATION_TAG: u16 = 0x0112;
+// Type of 16-bit unsigned integers
+const SHORT_TYPE: u16 = 3;
+
+fn read_exif(path: &Path) -> ViewerResult<Exif> {
+    let reader = Reader::new();
+    let result = match File::open(path) {
+        Ok(file) => reader.read_from_container(&mut BufReader::new(file)),
+        // Archive entries are read on memory
+        Err(_) => reader.read_from_container(&mut Cursor::new(read_file(path, None)?)),
+    };
+    result.map_err(ViewerError::decode)
+}
+
+// Returns "YYYY:MM:DD HH:MM:SS", which sorts chronologically as it is.
+pub fn get_capture_date(path: &Path) -> ViewerResult<String> {
+    let exif = read_exif(path)?;
+    let field = [Tag::DateTimeOriginal, Tag::DateTime]
+        .iter()
+        .find_map(|tag| exif.get_field(*tag, In::PRIMARY));
+    match field.map(|field| &field.value) {
+        Some(Value::Ascii(values)) if !values.is_empty() => {
+            Ok(String::from_utf8_lossy(&values[0]).to_string())
+        }
+        _ => Err(ViewerError::Decode(format!("No capture date: {:?}", path))),
+    }
+}
+
+fn read_orientation(exif: ViewerResult<Exif>) -> u16 {
+    match exif {
+        Ok(exif) => match exif.get_field(Tag::Orientation, In::PRIMARY) {
+            Some(field) => field.value.get_uint(0).unwrap_or(1) as u16,
+            None => 1,
+        },
+        Err(err) => {
+            log::debug!("{:?}", err);
+            1
+        }
+    }
+}
+
+// Images without the orientation tag are upright.
+pub fn get_orientation(path: &Path) -> u16 {
+    read_orientation(read_exif(path))
+}
+
+// Same as `get_orientation` for an image already read on memory
+pub fn parse_orientation(data: &[u8]) -> u16 {
+    let exif = Reader::new().read_from_container(&mut Cursor::new(data));
+    read_orientation(exif.map_err(ViewerError::decode))
+}
+
+fn read_u16(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes = [*data.get(offset)?, *data.get(offset + 1)?];
+    Some(match big_endian {
+        true => u16::from_be_bytes(bytes),
+        false => u16::from_le_bytes(bytes),
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = [
+        *data.get(offset)?,
+        *data.get(offset + 1)?,
+        *data.get(offset + 2)?,
+        *data.get(offset + 3)?,
+    ];
+    Some(match big_endian {
+        true => u32::from_be_bytes(bytes),
+        false => u32::from_le_bytes(bytes),
+    })
+}
+
+// Rewrites the orientation tag in the first IFD of TIFF data, if any
+fn patch_orientation(tiff: &mut [u8], orientation: u16) -> bool {
+    let big_endian = match tiff.get(0..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return false,
+    };
+    let ifd = match read_u32(tiff, 4, big_endian) {
+        Some(offset) => offset as usize,
+        None => return false,
+    };
+    let n_entries = read_u16(tiff, ifd, big_endian).unwrap_or(0) as usize;
+    for entry in (0..n_entries).map(|i| ifd + 2 + i * 12) {
+        match (
+            read_u16(tiff, entry, big_endian),
+            read_u16(tiff, entry + 2, big_endian),
+        ) {
+            (Some(ORIENTATION_TAG), Some(SHORT_TYPE)) if entry + 10 <= tiff.len() => {
+                let value = match big_endian {
+                    true => orientation.to_be_bytes(),
+                    false => orientation.to_le_bytes(),
+                };
+                tiff[entry + 8..entry + 10].copy_from_slice(&value);
+                return true;
+            }
+            _ => (),
+        }
+    }
+    false
+}
+
+// APP1 segment of EXIF data with the orientation tag only
+fn create_orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0\x2a\0\0\0\x08".to_vec();
+    tiff.extend(1u16.to_be_bytes());
+    tiff.extend(ORIENTATION_TAG.to_be_bytes());
+    tiff.extend(SHORT_TYPE.to_be_bytes());
+    tiff.extend(1u32.to_be_bytes());
+    tiff.extend(orientation.to_be_bytes());
+    tiff.extend([0, 0]);
+    // No more IFDs
+    tiff.extend(0u32.to_be_bytes());
+
+    let length = (2 + EXIF_HEADER.len() + tiff.len()) as u16;
+    let mut segment = vec![0xff, 0xe1];
+    segment.extend(length.to_be_bytes());
+    segment.extend(EXIF_HEADER);
+    segment.extend(tiff);
+    segment
+}
+
+// Returns the JPEG data with the orientation replaced. The image data is left as it is.
+fn replace_orientation(path: &Path, mut data: Vec<u8>, orientation: u16) -> ViewerResult<Vec<u8>> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return Err(ViewerError::UnsupportedFormat(path.to_path_buf()));
+    }
+
+    let (mut offset, mut insert_at) = (2, 2);
+    // Segments before the image data (SOS)
+    while data.get(offset) == Some(&0xff) && data.get(offset + 1) != Some(&0xda) {
+        let marker = data[offset + 1];
+        let length = match read_u16(&data, offset + 2, true) {
+            Some(length) if length >= 2 && offset + 2 + length as usize <= data.len() => {
+                length as usize
+            }
+            _ => return Err(ViewerError::Decode(format!("Broken segment in {:?}", path))),
+        };
+        let (start, end) = (offset + 4, offset + 2 + length);
+        // EXIF data follows JFIF data, which must come first
+        if marker == 0xe0 && offset == insert_at {
+            insert_at = end;
+        }
+        if marker == 0xe1 && data[start..end].starts_with(EXIF_HEADER) {
+            return match patch_orientation(&mut data[start + EXIF_HEADER.len()..end], orientation) {
+                true => Ok(data),
+                // Adding a tag would move the other EXIF data
+                false => Err(ViewerError::Decode(format!(
+                    "No orientation tag in {:?}",
+                    path
+                ))),
+            };
+        }
+        offset = end;
+    }
+
+    data.splice(
+        insert_at..insert_at,
+        create_orientation_segment(orientation),
+    );
+    Ok(data)
+}
+
+// Writes the EXIF orientation tag of a JPEG file
+pub fn set_orientation(path: &Path, orientation: u16) -> ViewerResult<()> {
+    // Archives are not rewritten
+    if split_archive_path(path).is_some() {
+        return Err(ViewerError::UnsupportedFormat(path.to_path_buf()));
+    }
+    // Symbolic links are kept, and the files they point to are rewritten
+    let target = canonicalize(path)?;
+    let data = replace_orientation(path, read(&target)?, orientation)?;
+
+    // The file is replaced at once not to be left broken. The temporary file is put beside it
+    // to be renamed, and removed if it is not.
+    let mut temp = NamedTempFile::new_in(target.parent().unwrap_or(Path::new(".")))?;
+    temp.write_all(&data)?;
+    temp.as_file()
+        .set_permissions(metadata(&target)?.permissions())?;
+    temp.persist(&target).map_err(|err| err.error)?;
+    log::info!("Orientation of {:?} is set to {:?}", path, orientation);
+    Ok(())
+}
+
+#[test]
+fn test_orientation() {
+    use std::fs::copy;
+
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(
+        get_orientation(Path::new("test_data/orientation/rotated.jpg")),
+        6
+    );
+    assert_eq!(
+        get_orientation(Path::new("test_data/orientation/plain.jpg")),
+        1
+    );
+
+    for name in ["rotated.jpg", "plain.jpg"] {
+        let path = dir.path().join(name);
+        copy(Path::new("test_data/orientation").join(name), &path).unwrap();
+        assert_eq!(set_orientation(&path, 8), Ok(()));
+        assert_eq!(get_orientation(&path), 8);
+        assert_eq!(set_orientation(&path, 1), Ok(()));
+        assert_eq!(get_orientation(&path), 1);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::fs::{read_dir, set_permissions, symlink_metadata, Permissions};
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let path = dir.path().join("rotated.jpg");
+        let link = dir.path().join("link.jpg");
+        set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+        symlink(&path, &link).unwrap();
+        assert_eq!(set_orientation(&link, 3), Ok(()));
+        assert_eq!(get_orientation(&path), 3);
+        assert!(symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        // No temporary files are left
+        assert_eq!(read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    // The EXIF segment is added after the JFIF one
+    let data = read(dir.path().join("plain.jpg")).unwrap();
+    assert_eq!(data[2..4], [0xff, 0xe0]);
+    assert_eq!(data[20..24], [0xff, 0xe1, 0, 34]);
+
+    let path = dir.path().join("a.png");
+    copy("test_data/image/a.png", &path).unwrap();
+    assert!(set_orientation(&path, 6).is_err());
+
+    // Broken segment lengths
+    for length in [[0, 1], [0xff, 0xff]] {
+        let mut data = read("test_data/orientation/plain.jpg").unwrap();
+        data[4..6].copy_from_slice(&length);
+        assert!(matches!(
+            replace_orientation(&path, data, 6),
+            Err(ViewerError::Decode(_))
+        ));
+    }
+}
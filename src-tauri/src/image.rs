@@ -1,31 +1,320 @@
-use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek};
 use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
 
-use base64;
+use ::image::io::Reader as ImageReader;
+use ::image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use ipc::{ImageSource, Transform};
 use mime;
 
-pub fn try_get_source_image(path: &PathBuf) -> Result<String, String> {
-    match path.extension() {
-        Some(ext) => {
-            let mimetype: mime::Mime = match ext.to_ascii_lowercase().to_str().unwrap() {
-                "bmp" => mime::IMAGE_BMP,
-                "jpg" => mime::IMAGE_JPEG,
-                "jpeg" => mime::IMAGE_JPEG,
-                "png" => mime::IMAGE_PNG,
-                "gif" => mime::IMAGE_GIF,
-                _ => {
-                    return Err(format!("Unsupported file: {:?}", path));
-                }
-            };
-
-            match fs::read(path.to_str().unwrap()) {
-                Ok(data) => {
-                    let b64data = base64::encode(data);
-                    Ok(format!("data:{};base64,{}", mimetype.to_string(), b64data))
-                }
-                Err(err) => Err(format!("{:?}", err)),
+use super::detect::ImageDetector;
+use super::error::{ViewerError, ViewerResult};
+use super::exif_data::{get_orientation, parse_orientation};
+use super::path::{get_file_info, is_file, read_file};
+use super::source::source_url;
+
+// Bytes read to find the size and the orientation, which are put before the image data
+const HEADER_SIZE: u64 = 1 << 18;
+
+fn parse_mimetype(mimetype: &str) -> mime::Mime {
+    mimetype.parse().unwrap()
+}
+
+pub fn get_mimetype(path: &PathBuf) -> ViewerResult<mime::Mime> {
+    // Non UTF-8 extensions are none of the supported ones
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => match ext.to_ascii_lowercase().as_str() {
+            "bmp" => Ok(mime::IMAGE_BMP),
+            "jpg" => Ok(mime::IMAGE_JPEG),
+            "jpeg" => Ok(mime::IMAGE_JPEG),
+            "jpe" => Ok(mime::IMAGE_JPEG),
+            "jfif" => Ok(mime::IMAGE_JPEG),
+            "png" => Ok(mime::IMAGE_PNG),
+            "gif" => Ok(mime::IMAGE_GIF),
+            "webp" => Ok(parse_mimetype("image/webp")),
+            "avif" => Ok(parse_mimetype("image/avif")),
+            "tif" => Ok(parse_mimetype("image/tiff")),
+            "tiff" => Ok(parse_mimetype("image/tiff")),
+            "ico" => Ok(parse_mimetype("image/x-icon")),
+            "tga" => Ok(parse_mimetype("image/x-tga")),
+            "pnm" => Ok(parse_mimetype("image/x-portable-anymap")),
+            "pbm" => Ok(parse_mimetype("image/x-portable-anymap")),
+            "pgm" => Ok(parse_mimetype("image/x-portable-anymap")),
+            "ppm" => Ok(parse_mimetype("image/x-portable-anymap")),
+            "qoi" => Ok(parse_mimetype("image/qoi")),
+            _ => Err(ViewerError::UnsupportedFormat(path.clone())),
+        },
+        None => Err(ViewerError::UnsupportedFormat(path.clone())),
+    }
+}
+
+// Formats which webviews cannot render by themselves. The others, including AVIF, which has
+// no decoder here, are served as they are.
+fn get_decode_format(mimetype: &mime::Mime) -> Option<ImageFormat> {
+    match mimetype.essence_str() {
+        "image/tiff" => Some(ImageFormat::Tiff),
+        "image/x-tga" => Some(ImageFormat::Tga),
+        "image/x-portable-anymap" => Some(ImageFormat::Pnm),
+        "image/qoi" => Some(ImageFormat::Qoi),
+        _ => None,
+    }
+}
+
+// Type of the payload served to the webview
+pub fn get_display_mimetype(mimetype: &mime::Mime) -> mime::Mime {
+    match get_decode_format(mimetype) {
+        Some(_) => mime::IMAGE_PNG,
+        None => mimetype.clone(),
+    }
+}
+
+// Flips first, then turns clockwise
+fn orient_image(img: DynamicImage, transform: Transform) -> DynamicImage {
+    let img = match transform.flipped {
+        true => img.fliph(),
+        false => img,
+    };
+    match transform.quarter_turns {
+        1 => img.rotate90(),
+        2 => img.rotate180(),
+        3 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// Decoded images are turned upright here, as PNG has no orientation for the webview to apply
+fn decode_image(data: &[u8], format: ImageFormat) -> ViewerResult<Vec<u8>> {
+    match ::image::load_from_memory_with_format(data, format) {
+        Ok(img) => {
+            let transform = Transform::from_orientation(parse_orientation(data));
+            let img = DynamicImage::ImageRgba8(orient_image(img, transform).into_rgba8());
+            let mut buffer = Cursor::new(Vec::new());
+            match img.write_to(&mut buffer, ImageOutputFormat::Png) {
+                Ok(_) => Ok(buffer.into_inner()),
+                Err(err) => Err(ViewerError::decode(err)),
             }
         }
-        None => Err("No extension".to_string()),
+        Err(err) => Err(ViewerError::decode(err)),
+    }
+}
+
+pub fn try_read_image(
+    path: &PathBuf,
+    mimetype: &mime::Mime,
+) -> ViewerResult<(mime::Mime, Vec<u8>)> {
+    let data = read_file(path, None)?;
+    match get_decode_format(mimetype) {
+        Some(format) => {
+            log::debug!("Decode {:?} as {:?}", path, format);
+            decode_image(&data, format).map(|png| (mime::IMAGE_PNG, png))
+        }
+        None => Ok((mimetype.clone(), data)),
+    }
+}
+
+pub fn detect_image(path: &PathBuf, detector: &ImageDetector) -> ViewerResult<mime::Mime> {
+    match detector.detect(path) {
+        Ok(mimetype) if is_file(path) => Ok(mimetype),
+        Ok(_) => Err(ViewerError::NotFound(path.clone())),
+        Err(err) => Err(err),
+    }
+}
+
+fn read_dimensions<R: BufRead + Seek>(
+    mut reader: ImageReader<R>,
+    mimetype: &mime::Mime,
+) -> ViewerResult<(u32, u32)> {
+    // Some formats (e.g. TGA) have no magic bytes to guess from
+    match ImageFormat::from_mime_type(mimetype.essence_str()).or(get_decode_format(mimetype)) {
+        Some(format) => reader.set_format(format),
+        None => match reader.with_guessed_format() {
+            Ok(guessed) => reader = guessed,
+            Err(err) => return Err(err.into()),
+        },
+    }
+    reader.into_dimensions().map_err(ViewerError::decode)
+}
+
+pub fn get_dimensions(path: &PathBuf, mimetype: &mime::Mime) -> ViewerResult<(u32, u32)> {
+    match File::open(path) {
+        Ok(file) => read_dimensions(ImageReader::new(BufReader::new(file)), mimetype),
+        // Archive entries are read on memory
+        Err(_) => match read_file(path, None) {
+            Ok(data) => read_dimensions(ImageReader::new(Cursor::new(data)), mimetype),
+            Err(err) => Err(err),
+        },
+    }
+}
+
+// Orientation and size as stored, read from the head of the file at once
+fn read_header(path: &PathBuf, mimetype: &mime::Mime) -> ViewerResult<(Transform, (u32, u32))> {
+    let header = read_file(path, Some(HEADER_SIZE))?;
+    let transform = Transform::from_orientation(parse_orientation(&header));
+    match read_dimensions(ImageReader::new(Cursor::new(&header)), mimetype) {
+        Ok(dimensions) => Ok((transform, dimensions)),
+        // The size may be put after long metadata
+        Err(_) if header.len() as u64 == HEADER_SIZE => {
+            get_dimensions(path, mimetype).map(|dimensions| (transform, dimensions))
+        }
+        Err(err) => Err(err),
     }
 }
+
+fn orient_dimensions(transform: Transform, (width, height): (u32, u32)) -> (u32, u32) {
+    match transform.is_transposed() {
+        true => (height, width),
+        false => (width, height),
+    }
+}
+
+// Width and height as the image is displayed
+pub fn get_oriented_dimensions(path: &PathBuf, mimetype: &mime::Mime) -> ViewerResult<(u32, u32)> {
+    read_header(path, mimetype)
+        .map(|(transform, dimensions)| orient_dimensions(transform, dimensions))
+}
+
+pub fn create_source(
+    token: u64,
+    path: &PathBuf,
+    mimetype: &mime::Mime,
+    index: usize,
+    count: usize,
+) -> ImageSource {
+    let (byte_size, modified) = match get_file_info(path) {
+        Ok(info) => (Some(info.size), info.modified),
+        Err(err) => {
+            log::info!("{:?}", err);
+            (None, None)
+        }
+    };
+    let (transform, dimensions) = match read_header(path, mimetype) {
+        Ok((transform, dimensions)) => (transform, Some(orient_dimensions(transform, dimensions))),
+        Err(err) => {
+            log::info!("{:?}", err);
+            (Transform::from_orientation(get_orientation(path)), None)
+        }
+    };
+
+    ImageSource {
+        url: source_url(token),
+        path: path.clone(),
+        file_name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        index,
+        count,
+        byte_size,
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        mimetype: get_display_mimetype(mimetype).to_string(),
+        // Decoded images are served upright
+        orientation: match get_decode_format(mimetype) {
+            Some(_) => Transform::default(),
+            None => transform,
+        },
+        modified: modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as u64),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_get_mimetype() {
+    let mimetype = |name: &str| get_mimetype(&PathBuf::from(name)).map(|m| m.to_string());
+
+    assert_eq!(mimetype("a.JPG"), Ok("image/jpeg".to_string()));
+    assert_eq!(mimetype("a.JFIF"), Ok("image/jpeg".to_string()));
+    assert_eq!(mimetype("a.webp"), Ok("image/webp".to_string()));
+    assert_eq!(mimetype("a.tif"), Ok("image/tiff".to_string()));
+    assert_eq!(mimetype("a.ppm"), Ok("image/x-portable-anymap".to_string()));
+    assert!(mimetype("a.txt").is_err());
+    assert!(mimetype("a").is_err());
+
+    let avif = get_mimetype(&PathBuf::from("a.avif")).unwrap();
+    assert_eq!(avif.essence_str(), "image/avif");
+    assert_eq!(get_display_mimetype(&avif), avif);
+
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = PathBuf::from(OsStr::from_bytes(b"a.\xff"));
+        assert_eq!(
+            get_mimetype(&path),
+            Err(ViewerError::UnsupportedFormat(path))
+        );
+    }
+}
+
+#[test]
+fn test_create_source() {
+    let path = PathBuf::from("test_data/archive/b.zip/c/a.png");
+    let source = create_source(3, &path, &mime::IMAGE_PNG, 0, 1);
+
+    assert_eq!(source.url, source_url(3));
+    assert_eq!(source.file_name, "a.png".to_string());
+    assert_eq!(source.byte_size, Some(86));
+    assert_eq!((source.width, source.height), (Some(2), Some(2)));
+    assert_eq!(source.mimetype, "image/png".to_string());
+    assert!(source.modified.is_some());
+
+    let tga = PathBuf::from("test_data/image/a.tga");
+    let tga_source = create_source(4, &tga, &get_mimetype(&tga).unwrap(), 0, 1);
+    assert_eq!((tga_source.width, tga_source.height), (Some(2), Some(2)));
+    assert_eq!(tga_source.mimetype, "image/png".to_string());
+}
+
+#[test]
+fn test_try_read_image() {
+    let png_signature = [0x89, b'P', b'N', b'G'];
+
+    let path1 = PathBuf::from("test_data/image/a.png");
+    let (mimetype1, data1) = try_read_image(&path1, &mime::IMAGE_PNG).unwrap();
+    assert_eq!(mimetype1, mime::IMAGE_PNG);
+    assert_eq!(data1, std::fs::read(&path1).unwrap());
+
+    for name in ["test_data/image/a.tga", "test_data/image/a.qoi"] {
+        let path = PathBuf::from(name);
+        let source_mimetype = get_mimetype(&path).unwrap();
+        assert_eq!(get_display_mimetype(&source_mimetype), mime::IMAGE_PNG);
+        let (mimetype, data) = try_read_image(&path, &source_mimetype).unwrap();
+        assert_eq!(mimetype, mime::IMAGE_PNG);
+        assert_eq!(data[..4], png_signature);
+    }
+}
+
+#[test]
+fn test_oriented_image() {
+    use ::image::GenericImageView;
+
+    let rotated = PathBuf::from("test_data/orientation/rotated.jpg");
+    let plain = PathBuf::from("test_data/orientation/plain.jpg");
+
+    // Turned clockwise from 3x2 pixels by the webview
+    let (mimetype, data) = try_read_image(&rotated, &mime::IMAGE_JPEG).unwrap();
+    assert_eq!(mimetype, mime::IMAGE_JPEG);
+    assert_eq!(data, std::fs::read(&rotated).unwrap());
+    let source = create_source(0, &rotated, &mime::IMAGE_JPEG, 0, 1);
+    assert_eq!((source.width, source.height), (Some(2), Some(3)));
+    assert_eq!(source.orientation, Transform::from_orientation(6));
+
+    let (_, data) = try_read_image(&plain, &mime::IMAGE_JPEG).unwrap();
+    assert_eq!(data, std::fs::read(&plain).unwrap());
+    let source = create_source(0, &plain, &mime::IMAGE_JPEG, 0, 1);
+    assert_eq!((source.width, source.height), (Some(3), Some(2)));
+    assert_eq!(source.orientation, Transform::default());
+
+    let img = DynamicImage::ImageRgb8(::image::RgbImage::from_fn(3, 2, |x, y| {
+        ::image::Rgb([x as u8, y as u8, 0])
+    }));
+    // Orientation 8 turns counterclockwise
+    let oriented = orient_image(img.clone(), Transform::from_orientation(8));
+    assert_eq!(oriented.get_pixel(0, 0), img.get_pixel(2, 0));
+    // Orientation 5 is transposed
+    let oriented = orient_image(img.clone(), Transform::from_orientation(5));
+    assert_eq!(oriented.get_pixel(1, 2), img.get_pixel(2, 1));
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+use ipc::SortConfig;
+
+use super::detect::ImageDetector;
+use super::path::{get_child_directories, get_child_files, is_directory};
+use super::sort::get_sort_elem;
+
+#[derive(Default)]
+struct IndexData {
+    paths: Vec<PathBuf>,
+    positions: HashMap<PathBuf, usize>,
+    complete: bool,
+}
+
+// All images under a root, in the order of the directory walk. The index is built in the
+// background, and is usable while it is being built.
+#[derive(Clone)]
+pub struct CollectionIndex {
+    root: PathBuf,
+    sort: SortConfig,
+    data: Arc<Mutex<IndexData>>,
+}
+
+fn build_index(
+    data: Weak<Mutex<IndexData>>,
+    root: PathBuf,
+    detector: ImageDetector,
+    sort: SortConfig,
+) {
+    let file_sort = |path: &PathBuf| get_sort_elem(path, sort.files, sort.seed);
+    let directory_sort = |path: &PathBuf| get_sort_elem(path, sort.directories, sort.seed);
+    // A file root is a collection of the single file
+    let is_tree = is_directory(&root);
+
+    // Directories left to walk, with the next one at the end. Siblings are listed once here,
+    // whereas `next_directory` lists them again on every step.
+    let mut pending = vec![root.clone()];
+    while let Some(dir) = pending.pop() {
+        let files = match is_tree {
+            true => get_child_files(&dir, &detector, &file_sort).unwrap_or_else(|err| {
+                log::info!("{:?}", err);
+                vec![]
+            }),
+            false => match detector.is_image(&root) {
+                true => vec![root.clone()],
+                false => vec![],
+            },
+        };
+
+        // The walk is abandoned once the index is dropped
+        match data.upgrade().map(|data| match data.lock() {
+            Ok(mut data) => {
+                for path in files {
+                    let position = data.paths.len();
+                    data.positions.insert(path.clone(), position);
+                    data.paths.push(path);
+                }
+                true
+            }
+            Err(_) => false,
+        }) {
+            Some(true) => (),
+            _ => return log::debug!("Abandon indexing {:?}", root),
+        }
+
+        if is_tree {
+            match get_child_directories(&dir, &directory_sort) {
+                Ok(dirs) => pending.extend(dirs.into_iter().rev()),
+                Err(err) => log::info!("{:?}", err),
+            }
+        }
+    }
+
+    if let Some(data) = data.upgrade() {
+        if let Ok(mut data) = data.lock() {
+            log::info!("Indexed {:?} images in {:?}", data.paths.len(), root);
+            data.complete = true;
+        }
+    }
+}
+
+impl CollectionIndex {
+    pub fn build(root: &Path, detector: &ImageDetector, sort: SortConfig) -> Self {
+        log::info!("Start indexing {:?}", root);
+        let data = Arc::new(Mutex::new(IndexData::default()));
+
+        let worker_data = Arc::downgrade(&data);
+        let (worker_root, worker_detector) = (root.to_path_buf(), detector.clone());
+        thread::spawn(move || build_index(worker_data, worker_root, worker_detector, sort));
+
+        Self {
+            root: root.to_path_buf(),
+            sort,
+            data,
+        }
+    }
+
+    // Whether the index follows the same walk
+    pub fn is_built_for(&self, root: &Path, sort: SortConfig) -> bool {
+        self.root == root && self.sort == sort
+    }
+
+    pub fn position(&self, path: &Path) -> Option<usize> {
+        match self.data.lock() {
+            Ok(data) => data.positions.get(path).copied(),
+            Err(_) => None,
+        }
+    }
+
+    pub fn get(&self, position: usize) -> Option<PathBuf> {
+        match self.data.lock() {
+            Ok(data) => data.paths.get(position).cloned(),
+            Err(_) => None,
+        }
+    }
+
+    // Number of images indexed so far
+    pub fn len(&self) -> usize {
+        match self.data.lock() {
+            Ok(data) => data.paths.len(),
+            Err(_) => 0,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        match self.data.lock() {
+            Ok(data) => data.complete,
+            Err(_) => false,
+        }
+    }
+
+    // Number of images, once the whole tree has been walked
+    pub fn total(&self) -> Option<usize> {
+        match self.is_complete() {
+            true => Some(self.len()),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub fn wait_index(index: &CollectionIndex) {
+    let start = std::time::Instant::now();
+    while !index.is_complete() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_collection_index() {
+    use super::detect::DetectPolicy;
+    use std::collections::HashSet;
+
+    let detector = ImageDetector::new(
+        DetectPolicy::Extension,
+        HashSet::from([String::from("txt")]),
+    );
+    let root = Path::new("test_data/state");
+    let index = CollectionIndex::build(root, &detector, SortConfig::default());
+    wait_index(&index);
+
+    let expected = [
+        "test_data/state/a/d.txt",
+        "test_data/state/a/a/a.txt",
+        "test_data/state/a/a/b.txt",
+        "test_data/state/a/a/c.txt",
+        "test_data/state/a/b/a.txt",
+        "test_data/state/a/b/b.txt",
+        "test_data/state/a/b/c.txt",
+        "test_data/state/b/a/a.txt",
+        "test_data/state/b/b/a.txt",
+        "test_data/state/c/a.txt",
+        "test_data/state/c/b.txt",
+        "test_data/state/c/c.txt",
+    ];
+    assert_eq!(index.total(), Some(expected.len()));
+    for (position, path) in expected.iter().enumerate() {
+        assert_eq!(index.get(position), Some(PathBuf::from(path)));
+        assert_eq!(index.position(Path::new(path)), Some(position));
+    }
+    assert_eq!(index.get(expected.len()), None);
+    assert!(index.is_built_for(root, SortConfig::default()));
+
+    // A file root
+    let root = Path::new("test_data/state/c/a.txt");
+    let index = CollectionIndex::build(root, &detector, SortConfig::default());
+    wait_index(&index);
+    assert_eq!(index.total(), Some(1));
+    assert_eq!(index.position(root), Some(0));
+}
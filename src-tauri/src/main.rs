@@ -5,46 +5,252 @@
 
 use env_logger;
 use serde_json::Value;
-use std::{collections::HashSet, sync::Mutex};
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{atomic::AtomicU32, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tauri::Manager;
 
+mod archive;
+mod cache;
 mod command;
+mod detect;
+mod error;
+mod exif_data;
 mod image;
+mod index;
 mod path;
+mod prefetch;
+mod protocol;
+mod session;
+mod sort;
+mod source;
 mod state;
 mod utils;
 
-static AVAILABLE_EXTENSIONS: [&str; 5] = ["bmp", "jpg", "jpeg", "png", "gif"];
+static AVAILABLE_EXTENSIONS: [&str; 18] = [
+    "bmp", "jpg", "jpeg", "jpe", "jfif", "png", "gif", "webp", "avif", "tif", "tiff", "ico", "tga",
+    "pnm", "pbm", "pgm", "ppm", "qoi",
+];
 
-pub struct ViewerStateManager(Mutex<state::ViewerState>);
+pub struct ViewerStateManager(Mutex<utils::Versioned<state::ViewerState>>);
+pub struct SourceRegistryManager(Mutex<source::SourceRegistry>);
+pub struct PrefetcherManager(prefetch::Prefetcher);
+// Latest navigation request id sent from the frontend
+pub struct RequestManager(AtomicU32);
+// Saved sessions, and whether to restore them
+pub struct SessionManager(Mutex<session::Session>, bool);
+
+const DEFAULT_PREFETCH: usize = 2;
+const DEFAULT_CACHE_SIZE_MB: usize = 256;
+const SESSION_SAVE_DELAY: Duration = Duration::from_secs(3);
+
+fn get_parsed_arg<T>(matches: &tauri::api::cli::Matches, name: &str, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    match matches.args.get(name).map(|arg| arg.value.clone()) {
+        Some(Value::String(value)) => value.parse().unwrap_or_else(|err| {
+            log::warn!("Invalid {:?} value {:?}: {:?}", name, value, err);
+            default
+        }),
+        _ => default,
+    }
+}
+
+// Globs are expanded and relative paths are resolved against the working directory
+fn get_paths_arg(matches: &tauri::api::cli::Matches, name: &str) -> Vec<PathBuf> {
+    let args: Vec<String> = match matches.args.get(name).map(|arg| arg.value.clone()) {
+        Some(Value::Array(values)) => values
+            .into_iter()
+            .filter_map(|value| match value {
+                Value::String(arg) => Some(arg),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+    path::expand_paths(&args)
+        .iter()
+        .map(|path| path::to_absolute(path))
+        .collect()
+}
+
+fn is_flag_set(matches: &tauri::api::cli::Matches, name: &str) -> bool {
+    matches!(
+        matches.args.get(name).map(|arg| arg.value.clone()),
+        Some(Value::Bool(true))
+    )
+}
+
+fn load_last_session() -> session::Session {
+    match session::get_session_path().map(|path| session::load_session(&path)) {
+        Some(Ok(session)) => session,
+        Some(Err(err)) => {
+            log::info!("Failed to load the session: {:?}", err);
+            session::Session::default()
+        }
+        None => session::Session::default(),
+    }
+}
+
+fn save_current_session(session_manager: &SessionManager, state_manager: &ViewerStateManager) {
+    // The session is kept locked while saving not to write the file concurrently
+    let mut session = match session_manager.0.lock() {
+        Ok(session) => session,
+        Err(err) => return log::warn!("{:?}", err),
+    };
+    match state_manager.0.lock() {
+        Ok(state) => session.record(&state.value),
+        Err(err) => return log::warn!("{:?}", err),
+    }
+    if let Some(path) = session::get_session_path() {
+        if let Err(err) = session::save_session(&path, &session) {
+            log::warn!("Failed to save the session: {:?}", err);
+        }
+    }
+}
+
+// Save the session once the navigation has settled, so that a crash loses little
+fn watch_session(app: tauri::AppHandle) {
+    let generation = |app: &tauri::AppHandle| match app.state::<ViewerStateManager>().0.lock() {
+        Ok(state) => Some(state.generation),
+        Err(_) => None,
+    };
+    let mut saved = generation(&app);
+    let mut last = saved;
+    loop {
+        thread::sleep(SESSION_SAVE_DELAY);
+        let current = generation(&app);
+        if current.is_none() {
+            return;
+        }
+        if current != saved && current == last {
+            save_current_session(
+                &app.state::<SessionManager>(),
+                &app.state::<ViewerStateManager>(),
+            );
+            saved = current;
+        }
+        last = current;
+    }
+}
 
 fn main() {
     env_logger::init();
     tauri::Builder::default()
+        .manage(SourceRegistryManager(Mutex::new(
+            source::SourceRegistry::default(),
+        )))
+        .manage(RequestManager(AtomicU32::new(0)))
+        .register_uri_scheme_protocol(source::PROTOCOL, protocol::handle_viewer_protocol)
         .setup(move |app| match app.get_cli_matches() {
-            Ok(matches) => match matches.args.get("filename").unwrap().value.clone() {
-                Value::String(filename) => {
-                    let mut state = state::ViewerState::new(
-                        &filename,
-                        HashSet::from(AVAILABLE_EXTENSIONS.map(|s| s.to_string())),
-                    );
-                    match state.reload_files() {
-                        Ok(_) => {
-                            app.manage(ViewerStateManager(Mutex::new(state)));
-                            Ok(())
-                        }
-                        Err(err) => Err(err.into()),
+            Ok(matches) => {
+                let session = load_last_session();
+                let restore = !is_flag_set(&matches, "ignore-session");
+                let paths = match get_paths_arg(&matches, "paths") {
+                    paths if paths.len() > 0 => paths,
+                    // The last image is opened only if it still exists
+                    _ if is_flag_set(&matches, "resume") => session
+                        .last
+                        .iter()
+                        .filter(|last| path::is_file(last))
+                        .cloned()
+                        .collect(),
+                    // The frontend shows a directory browser when nothing is opened
+                    _ => vec![],
+                };
+                let mut state = state::ViewerState::new(
+                    &paths,
+                    HashSet::from(AVAILABLE_EXTENSIONS.map(|s| s.to_string())),
+                );
+                if let Some(Value::String(root)) =
+                    matches.args.get("root").map(|arg| arg.value.clone())
+                {
+                    match state.set_root(&path::to_absolute(Path::new(&root))) {
+                        Ok(_) | Err(error::ViewerError::NothingOpened) => (),
+                        Err(err) => return Err(err.into()),
                     }
                 }
-                _ => Err("Filename is required".into()),
-            },
+                match matches.args.get("detect").map(|arg| arg.value.clone()) {
+                    Some(Value::String(policy)) => match policy.parse() {
+                        Ok(policy) => state.set_detect_policy(policy),
+                        Err(err) => return Err(err.into()),
+                    },
+                    _ => state.set_detect_policy(detect::DetectPolicy::default()),
+                }
+                state.set_wrap(is_flag_set(&matches, "wrap"));
+                state.set_recursive(is_flag_set(&matches, "recursive"));
+                state.set_sort(ipc::SortConfig {
+                    seed: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|duration| duration.as_nanos() as u64)
+                        .unwrap_or_default(),
+                    ..ipc::SortConfig::default()
+                });
+                if restore {
+                    session.restore(&mut state);
+                }
+                // Explicit arguments take precedence over the saved session
+                let sort = state.sort();
+                state.set_sort(ipc::SortConfig {
+                    files: get_parsed_arg(&matches, "sort", sort.files),
+                    directories: get_parsed_arg(&matches, "dir-sort", sort.directories),
+                    seed: get_parsed_arg(&matches, "seed", sort.seed),
+                });
+                let cache_size_mb = get_parsed_arg(&matches, "cache-size", DEFAULT_CACHE_SIZE_MB);
+                let cache_size = match cache_size_mb.checked_mul(1 << 20) {
+                    Some(cache_size) => cache_size,
+                    None => {
+                        let message = format!("Too large cache size: {:?} MB", cache_size_mb);
+                        return Err(error::ViewerError::InvalidArgument(message).into());
+                    }
+                };
+                let prefetcher = prefetch::Prefetcher::new(
+                    get_parsed_arg(&matches, "prefetch", DEFAULT_PREFETCH),
+                    cache_size,
+                );
+                match state.reload_files() {
+                    Ok(_) | Err(error::ViewerError::NothingOpened) => {
+                        let state = utils::Versioned::new(state);
+                        app.manage(PrefetcherManager(prefetcher));
+                        app.manage(ViewerStateManager(Mutex::new(state)));
+                        app.manage(SessionManager(Mutex::new(session), restore));
+                        let handle = app.handle();
+                        thread::spawn(move || watch_session(handle));
+                        Ok(())
+                    }
+                    Err(err) => Err(err.into()),
+                }
+            }
             Err(err) => Err(err.into()),
         })
+        .on_window_event(|event| match event.event() {
+            tauri::WindowEvent::Destroyed => save_current_session(
+                &event.window().state::<SessionManager>(),
+                &event.window().state::<ViewerStateManager>(),
+            ),
+            _ => (),
+        })
         .invoke_handler(tauri::generate_handler![
             command::next_image,
             command::prev_image,
             command::next_directory,
             command::prev_directory,
+            command::change_sort,
+            command::change_spread,
+            command::jump,
+            command::open_paths,
+            command::list_directory,
+            command::get_view,
+            command::set_view,
+            command::save_orientation,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -1,73 +1,203 @@
 use std::cmp::Ordering;
-use std::collections::HashSet;
 use std::collections::VecDeque;
-use std::fs::read_dir;
-use std::path::{Path, PathBuf};
+use std::env::current_dir;
+use std::fs::{metadata, read_dir, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
 
-fn get_children<F, G, T>(
-    parent: &Path,
-    predicate: &F,
-    sort_elem: &G,
-) -> Result<Vec<PathBuf>, String>
+use glob::glob;
+
+use super::archive::{
+    get_archive_children, get_archive_entry_size, has_archive_entry, is_archive,
+    read_archive_entry, split_archive_path,
+};
+use super::detect::ImageDetector;
+use super::error::ViewerResult;
+
+pub fn is_directory(path: &Path) -> bool {
+    path.is_dir()
+        || match split_archive_path(path) {
+            Some((archive, inner)) => {
+                inner.as_os_str().is_empty()
+                    || match get_archive_children(&archive, inner.parent().unwrap()) {
+                        Ok(children) => children.contains(&(path.to_path_buf(), true)),
+                        Err(_) => false,
+                    }
+            }
+            None => false,
+        }
+}
+
+pub fn is_file(path: &Path) -> bool {
+    match split_archive_path(path) {
+        Some((archive, inner)) => has_archive_entry(&archive, &inner),
+        None => path.is_file(),
+    }
+}
+
+// Relative paths would have an empty parent at the top of the tree. "." and ".." are
+// resolved without following links, as paths in archives do not exist on the disk.
+pub fn to_absolute(path: &Path) -> PathBuf {
+    let path = match current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_path_buf(),
+    };
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// Arguments which are not found as they are are expanded as shell-style globs, in case the
+// shell has not expanded them.
+pub fn expand_paths(args: &[String]) -> Vec<PathBuf> {
+    args.iter()
+        .flat_map(|arg| {
+            let path = PathBuf::from(arg);
+            if path.exists() || is_file(&path) || is_directory(&path) {
+                return vec![path];
+            }
+            match glob(arg) {
+                Ok(paths) => {
+                    let paths: Vec<PathBuf> = paths.filter_map(|path| path.ok()).collect();
+                    if paths.is_empty() {
+                        log::warn!("No such file: {:?}", arg);
+                    }
+                    paths
+                }
+                Err(err) => {
+                    log::warn!("Invalid pattern {:?}: {}", arg, err);
+                    vec![]
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn read_file(path: &Path, limit: Option<u64>) -> ViewerResult<Vec<u8>> {
+    match split_archive_path(path) {
+        Some((archive, inner)) => read_archive_entry(&archive, &inner, limit),
+        None => match File::open(path) {
+            Ok(file) => {
+                let mut data = vec![];
+                match file.take(limit.unwrap_or(u64::MAX)).read_to_end(&mut data) {
+                    Ok(_) => Ok(data),
+                    Err(err) => Err(err.into()),
+                }
+            }
+            Err(err) => Err(err.into()),
+        },
+    }
+}
+
+pub struct FileInfo {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub created: Option<SystemTime>,
+}
+
+// Archive entries share the timestamps of the archive.
+pub fn get_file_info(path: &Path) -> ViewerResult<FileInfo> {
+    let (size, meta) = match split_archive_path(path) {
+        Some((archive, inner)) => (
+            get_archive_entry_size(&archive, &inner)?,
+            metadata(&archive)?,
+        ),
+        None => {
+            let meta = metadata(path)?;
+            (meta.len(), meta)
+        }
+    };
+    Ok(FileInfo {
+        size,
+        modified: meta.modified().ok(),
+        created: meta.created().ok(),
+    })
+}
+
+// Returns (path, is_dir) pairs; archives are listed as directories.
+fn list_directory(parent: &Path) -> ViewerResult<Vec<(PathBuf, bool)>> {
+    match split_archive_path(parent) {
+        Some((archive, inner)) => get_archive_children(&archive, &inner),
+        None => match read_dir(parent) {
+            Ok(entries) => Ok(entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .map(|path| {
+                    let is_dir = path.is_dir() || is_archive(&path);
+                    (path, is_dir)
+                })
+                .collect()),
+            Err(err) => Err(err.into()),
+        },
+    }
+}
+
+fn get_children<F, G, T>(parent: &Path, predicate: &F, sort_elem: &G) -> ViewerResult<Vec<PathBuf>>
 where
-    F: Fn(&PathBuf) -> bool,
+    F: Fn(&PathBuf, bool) -> bool,
     G: Fn(&PathBuf) -> T,
     T: Ord,
 {
-    match read_dir(parent) {
+    match list_directory(parent) {
         Ok(entries) => {
             let mut paths: Vec<PathBuf> = entries
-                .filter_map(|entry| entry.ok())
-                .map(|entry| entry.path())
-                .filter(predicate)
+                .into_iter()
+                .filter(|(path, is_dir)| predicate(path, *is_dir))
+                .map(|(path, _)| path)
                 .collect();
-            paths.sort_unstable_by(|p1, p2| sort_elem(p1).partial_cmp(&sort_elem(p2)).unwrap());
+            // Sort keys may read file metadata, so they are computed once per path
+            paths.sort_by_cached_key(|path| sort_elem(path));
             log::debug!("{:?} entries are found in {:?}", paths.len(), parent);
             Ok(paths)
         }
-        Err(err) => Err(err.to_string()),
+        Err(err) => Err(err),
     }
 }
 
 pub fn get_child_files<F, T>(
     parent: &Path,
-    extensions: &HashSet<String>,
+    detector: &ImageDetector,
     sort_elem: &F,
-) -> Result<Vec<PathBuf>, String>
+) -> ViewerResult<Vec<PathBuf>>
 where
     F: Fn(&PathBuf) -> T,
     T: Ord,
 {
     get_children(
         parent,
-        &|path| {
-            path.is_file()
-                && match path.extension() {
-                    Some(ext) => match ext.to_str() {
-                        Some(extension) => extensions.contains(&extension.to_ascii_lowercase()),
-                        _ => false,
-                    },
-                    _ => false,
-                }
-        },
+        &|path, is_dir| !is_dir && detector.is_image(path),
         sort_elem,
     )
 }
 
-pub fn get_child_directories<F, T>(parent: &Path, sort_elem: &F) -> Result<Vec<PathBuf>, String>
+pub fn get_child_directories<F, T>(parent: &Path, sort_elem: &F) -> ViewerResult<Vec<PathBuf>>
 where
     F: Fn(&PathBuf) -> T,
     T: Ord,
 {
-    get_children(parent, &|path| path.is_dir(), sort_elem)
+    get_children(parent, &|_, is_dir| is_dir, sort_elem)
 }
 
-pub fn next_directory<F, T>(path: &PathBuf, sort_elem: &F) -> Option<PathBuf>
+// Whether the directory walk can move from `path` up to its parent without leaving `root`
+fn can_ascend(path: &Path, parent: &Path, root: &Path) -> bool {
+    path != root && parent.starts_with(root)
+}
+
+pub fn next_directory<F, T>(path: &PathBuf, root: &Path, sort_elem: &F) -> Option<PathBuf>
 where
     F: Fn(&PathBuf) -> T,
     T: Ord,
 {
-    log::debug!("Explore next directory for {:?}", path);
+    log::debug!("Explore next directory for {:?} in {:?}", path, root);
 
     if let Ok(dirs) = get_child_directories(&path, sort_elem) {
         if let Some(next_dir) = VecDeque::from(dirs).pop_front() {
@@ -76,11 +206,14 @@ where
     }
 
     let mut current = path.clone();
-    while let Some(parent) = current.parent() {
+    while let Some(parent) = current
+        .parent()
+        .filter(|parent| can_ascend(&current, parent, root))
+    {
         match get_child_directories(&parent, sort_elem) {
             Ok(mut dirs) => {
                 if let Some(cursor) = dirs.iter().position(|dir| {
-                    sort_elem(dir).partial_cmp(&sort_elem(path)) == Some(Ordering::Greater)
+                    sort_elem(dir).partial_cmp(&sort_elem(&current)) == Some(Ordering::Greater)
                 }) {
                     return Some(dirs.remove(cursor));
                 }
@@ -93,31 +226,41 @@ where
     None
 }
 
-pub fn prev_directory<F, T>(path: &PathBuf, sort_elem: &F) -> Option<PathBuf>
+// The last directory under `dir` in the traversal order, which is `dir` itself if it has no
+// child directories
+pub fn last_descendant<F, T>(dir: PathBuf, sort_elem: &F) -> PathBuf
+where
+    F: Fn(&PathBuf) -> T,
+    T: Ord,
+{
+    let mut dir = dir;
+    while let Ok(mut children) = get_child_directories(&dir, sort_elem) {
+        match children.pop() {
+            Some(child) => dir = child,
+            None => break,
+        }
+    }
+    dir
+}
+
+pub fn prev_directory<F, T>(path: &PathBuf, root: &Path, sort_elem: &F) -> Option<PathBuf>
 where
     F: Fn(&PathBuf) -> T,
     T: Ord,
 {
-    log::debug!("Explore prev directory for {:?}", path);
+    log::debug!("Explore prev directory for {:?} in {:?}", path, root);
 
-    if let Some(parent) = path.parent() {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| can_ascend(path, parent, root))
+    {
         match get_child_directories(&parent, sort_elem) {
             Ok(mut dirs) => {
                 dirs.reverse();
                 match dirs.iter().position(|dir| {
                     sort_elem(dir).partial_cmp(&sort_elem(path)) == Some(Ordering::Less)
                 }) {
-                    Some(cursor) => loop {
-                        let mut dir = dirs.remove(cursor);
-                        while let Ok(mut children) = get_child_directories(&dir, sort_elem) {
-                            if children.len() > 0 {
-                                dir = children.pop().unwrap();
-                            } else {
-                                return Some(dir);
-                            }
-                        }
-                        return Some(dir);
-                    },
+                    Some(cursor) => return Some(last_descendant(dirs.remove(cursor), sort_elem)),
                     None => return Some(parent.to_path_buf()),
                 }
             }
@@ -127,6 +270,21 @@ where
     None
 }
 
+#[test]
+fn test_to_absolute() {
+    let dir = current_dir().unwrap();
+    assert_eq!(
+        to_absolute(Path::new("test_data/./state/a/../b")),
+        dir.join("test_data/state/b")
+    );
+    assert!(to_absolute(Path::new("test_data/state/b"))
+        .starts_with(to_absolute(Path::new("test_data/state/a/.."))));
+    assert_eq!(
+        to_absolute(Path::new("/a/b.zip/../c")),
+        PathBuf::from("/a/c")
+    );
+}
+
 /*
  * Test data structure:
  * test_data/state/ +- a/ +- a/ +- a
@@ -149,9 +307,35 @@ where
  *                        +- b
  *                        +- c
  */
+#[test]
+fn test_expand_paths() {
+    let args = [
+        "test_data/state/c",
+        "test_data/state/a/a/*.txt",
+        "test_data/archive/b.zip/c",
+        "test_data/state/x*",
+    ];
+    assert_eq!(
+        expand_paths(&args.map(String::from)),
+        vec![
+            PathBuf::from("test_data/state/c"),
+            PathBuf::from("test_data/state/a/a/a.txt"),
+            PathBuf::from("test_data/state/a/a/b.txt"),
+            PathBuf::from("test_data/state/a/a/c.txt"),
+            PathBuf::from("test_data/archive/b.zip/c"),
+        ]
+    );
+}
+
 #[test]
 fn test_get_child_files() {
-    let extensions = HashSet::from([String::from("txt")]);
+    use super::detect::DetectPolicy;
+    use std::collections::HashSet;
+
+    let detector = ImageDetector::new(
+        DetectPolicy::Extension,
+        HashSet::from([String::from("txt")]),
+    );
     let sort_elem = |path: &PathBuf| path.clone();
     let get_filenames = |paths: Vec<PathBuf>| {
         paths
@@ -161,7 +345,7 @@ fn test_get_child_files() {
     };
 
     let parent1 = Path::new("test_data/state/a/a");
-    let actual1 = get_child_files(parent1, &extensions, &sort_elem);
+    let actual1 = get_child_files(parent1, &detector, &sort_elem);
     assert!(actual1.is_ok());
     assert_eq!(
         get_filenames(actual1.unwrap()),
@@ -173,7 +357,7 @@ fn test_get_child_files() {
     );
 
     let parent2 = Path::new("test_data/state/a");
-    let actual2 = get_child_files(parent2, &extensions, &sort_elem);
+    let actual2 = get_child_files(parent2, &detector, &sort_elem);
     assert!(actual2.is_ok());
     assert_eq!(
         get_filenames(actual2.unwrap()),
@@ -181,7 +365,7 @@ fn test_get_child_files() {
     );
 
     let parent3 = Path::new("test_data/state/a/c");
-    let actual3 = get_child_files(parent3, &extensions, &sort_elem);
+    let actual3 = get_child_files(parent3, &detector, &sort_elem);
     let expected3: Vec<String> = vec![];
     assert!(actual3.is_ok());
     assert_eq!(get_filenames(actual3.unwrap()), expected3);
@@ -219,45 +403,145 @@ fn test_get_directories() {
 #[test]
 fn test_next_directory() {
     let sort_elem = |path: &PathBuf| path.clone();
+    let root = Path::new("test_data/state");
 
     let path1 = Path::new("test_data/state/a");
     let expected1 = "test_data/state/a/a";
-    let actual1 = next_directory(&path1.to_path_buf(), &sort_elem);
+    let actual1 = next_directory(&path1.to_path_buf(), root, &sort_elem);
     assert!(actual1.is_some());
     assert_eq!(actual1.unwrap().to_str().unwrap(), expected1);
 
     let path2 = Path::new("test_data/state/a/b");
     let expected2 = "test_data/state/a/c";
-    let actual2 = next_directory(&path2.to_path_buf(), &sort_elem);
+    let actual2 = next_directory(&path2.to_path_buf(), root, &sort_elem);
     assert!(actual2.is_some());
     assert_eq!(actual2.unwrap().to_str().unwrap(), expected2);
 
     let path3 = Path::new("test_data/state/a/c");
     let expected3 = "test_data/state/b";
-    let actual3 = next_directory(&path3.to_path_buf(), &sort_elem);
+    let actual3 = next_directory(&path3.to_path_buf(), root, &sort_elem);
     assert!(actual3.is_some());
     assert_eq!(actual3.unwrap().to_str().unwrap(), expected3);
+
+    // Siblings of the root are not visited
+    let path4 = Path::new("test_data/state/c");
+    assert_eq!(next_directory(&path4.to_path_buf(), root, &sort_elem), None);
+    let root4 = Path::new("test_data/state/a");
+    assert_eq!(
+        next_directory(&path2.to_path_buf(), root4, &sort_elem),
+        Some(Path::new("test_data/state/a/c").to_path_buf())
+    );
+    assert_eq!(
+        next_directory(&path3.to_path_buf(), root4, &sort_elem),
+        None
+    );
 }
 
 #[test]
 fn test_prev_directory() {
     let sort_elem = |path: &PathBuf| path.clone();
+    let root = Path::new("test_data/state");
 
     let path1 = Path::new("test_data/state/a/a");
     let expected1 = "test_data/state/a";
-    let actual1 = prev_directory(&path1.to_path_buf(), &sort_elem);
+    let actual1 = prev_directory(&path1.to_path_buf(), root, &sort_elem);
     assert!(actual1.is_some());
     assert_eq!(actual1.unwrap().to_str().unwrap(), expected1);
 
     let path2 = Path::new("test_data/state/a/c");
     let expected2 = "test_data/state/a/b";
-    let actual2 = prev_directory(&path2.to_path_buf(), &sort_elem);
+    let actual2 = prev_directory(&path2.to_path_buf(), root, &sort_elem);
     assert!(actual2.is_some());
     assert_eq!(actual2.unwrap().to_str().unwrap(), expected2);
 
     let path3 = Path::new("test_data/state/b");
     let expected3 = "test_data/state/a/c";
-    let actual3 = prev_directory(&path3.to_path_buf(), &sort_elem);
+    let actual3 = prev_directory(&path3.to_path_buf(), root, &sort_elem);
     assert!(actual3.is_some());
     assert_eq!(actual3.unwrap().to_str().unwrap(), expected3);
+
+    assert_eq!(
+        last_descendant(root.to_path_buf(), &sort_elem),
+        Path::new("test_data/state/c").to_path_buf()
+    );
+    assert_eq!(
+        last_descendant(Path::new("test_data/state/a").to_path_buf(), &sort_elem),
+        Path::new("test_data/state/a/c").to_path_buf()
+    );
+
+    // The root has no previous directory
+    assert_eq!(prev_directory(&root.to_path_buf(), root, &sort_elem), None);
+    let root4 = Path::new("test_data/state/b");
+    assert_eq!(
+        prev_directory(&path3.to_path_buf(), root4, &sort_elem),
+        None
+    );
+}
+
+/*
+ * Test data structure:
+ * test_data/archive/ +- a/ +- a.png
+ *                    |
+ *                    +- b.zip/ +- a.png
+ *                    |         +- b.png
+ *                    |         +- c/ +- a.png
+ *                    |         +- d.txt
+ *                    |
+ *                    +- c/ +- a.png
+ */
+#[test]
+fn test_archive_directory() {
+    use super::detect::DetectPolicy;
+    use std::collections::HashSet;
+
+    let detector = ImageDetector::new(
+        DetectPolicy::Extension,
+        HashSet::from([String::from("png")]),
+    );
+    let sort_elem = |path: &PathBuf| path.clone();
+    let get_filenames = |paths: Vec<PathBuf>| {
+        paths
+            .iter()
+            .map(|path| path.to_str().unwrap().to_string())
+            .collect::<Vec<String>>()
+    };
+
+    let archive = Path::new("test_data/archive/b.zip");
+    let root = Path::new("test_data/archive");
+    assert!(is_directory(archive));
+    assert!(is_directory(&archive.join("c")));
+    assert!(!is_directory(&archive.join("a.png")));
+    assert!(is_file(&archive.join("a.png")));
+    assert!(!is_file(archive));
+
+    let actual1 = get_child_directories(Path::new("test_data/archive"), &sort_elem);
+    assert_eq!(
+        get_filenames(actual1.unwrap()),
+        vec![
+            "test_data/archive/a".to_string(),
+            "test_data/archive/b.zip".to_string(),
+            "test_data/archive/c".to_string(),
+        ]
+    );
+
+    let actual2 = get_child_files(archive, &detector, &sort_elem);
+    assert_eq!(
+        get_filenames(actual2.unwrap()),
+        vec![
+            "test_data/archive/b.zip/a.png".to_string(),
+            "test_data/archive/b.zip/b.png".to_string(),
+        ]
+    );
+
+    let actual3 = next_directory(&archive.to_path_buf(), root, &sort_elem);
+    assert_eq!(actual3, Some(archive.join("c")));
+
+    let actual4 = next_directory(&archive.join("c"), root, &sort_elem);
+    assert_eq!(actual4, Some(PathBuf::from("test_data/archive/c")));
+
+    let actual5 = prev_directory(&PathBuf::from("test_data/archive/c"), root, &sort_elem);
+    assert_eq!(actual5, Some(archive.join("c")));
+
+    let actual6 = prev_directory(&archive.join("c"), root, &sort_elem);
+    assert_eq!(actual6, Some(archive.to_path_buf()));
 }
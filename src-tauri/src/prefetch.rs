@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use mime;
+
+use super::cache::ImageCache;
+use super::error::ViewerResult;
+use super::image::try_read_image;
+use super::state::ViewerState;
+
+// Paths around the cursor in the order of next_cursor/prev_cursor, nearest first.
+fn get_neighbours(state: &ViewerState, n_prefetch: usize) -> Vec<PathBuf> {
+    let mut next_state = state.clone();
+    let mut prev_state = state.clone();
+    let mut neighbours = vec![];
+    for _ in 0..n_prefetch {
+        if next_state.next_cursor().is_ok() {
+            if let Ok(path) = next_state.get() {
+                neighbours.push(path);
+            }
+        }
+        if prev_state.prev_cursor().is_ok() {
+            if let Ok(path) = prev_state.get() {
+                neighbours.push(path);
+            }
+        }
+    }
+    neighbours
+}
+
+fn load_image(
+    cache: &Mutex<ImageCache>,
+    path: &PathBuf,
+    mimetype: &mime::Mime,
+) -> ViewerResult<(mime::Mime, Arc<Vec<u8>>)> {
+    if let Ok(mut cache) = cache.lock() {
+        if let Some(cached) = cache.get(path) {
+            return Ok(cached);
+        }
+    }
+
+    match try_read_image(path, mimetype) {
+        Ok((display_mimetype, data)) => {
+            let data = Arc::new(data);
+            if let Ok(mut cache) = cache.lock() {
+                cache.insert(path, &display_mimetype, data.clone());
+            }
+            Ok((display_mimetype, data))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn run_prefetch(receiver: Receiver<ViewerState>, cache: Arc<Mutex<ImageCache>>, n_prefetch: usize) {
+    let mut pending: Option<ViewerState> = None;
+    loop {
+        let state = match pending.take() {
+            Some(state) => state,
+            None => match receiver.recv() {
+                Ok(state) => state,
+                Err(_) => return,
+            },
+        };
+
+        for path in get_neighbours(&state, n_prefetch) {
+            // Abandon the current request when the cursor has already moved
+            while let Ok(latest) = receiver.try_recv() {
+                pending = Some(latest);
+            }
+            if pending.is_some() {
+                break;
+            }
+
+            let cached = match cache.lock() {
+                Ok(cache) => cache.contains(&path),
+                Err(_) => return,
+            };
+            if !cached {
+                match state.detector().detect(&path) {
+                    Ok(mimetype) => match load_image(&cache, &path, &mimetype) {
+                        Ok(_) => log::debug!("Prefetched {:?}", path),
+                        Err(err) => log::info!("{:?}", err),
+                    },
+                    Err(err) => log::info!("{:?}", err),
+                }
+            }
+        }
+    }
+}
+
+pub struct Prefetcher {
+    cache: Arc<Mutex<ImageCache>>,
+    sender: Mutex<Sender<ViewerState>>,
+}
+
+impl Prefetcher {
+    pub fn new(n_prefetch: usize, budget: usize) -> Self {
+        log::info!("Prefetch {:?} images around the cursor", n_prefetch);
+        let cache = Arc::new(Mutex::new(ImageCache::new(budget)));
+        let (sender, receiver) = channel();
+
+        let worker_cache = cache.clone();
+        thread::spawn(move || run_prefetch(receiver, worker_cache, n_prefetch));
+
+        Self {
+            cache,
+            sender: Mutex::new(sender),
+        }
+    }
+
+    pub fn request(&self, state: &ViewerState) {
+        match self.sender.lock() {
+            Ok(sender) => {
+                if let Err(err) = sender.send(state.clone()) {
+                    log::info!("{:?}", err);
+                }
+            }
+            Err(err) => log::info!("{:?}", err),
+        }
+    }
+
+    // Drops the cached payload of a file which has been changed
+    pub fn invalidate(&self, path: &PathBuf) {
+        match self.cache.lock() {
+            Ok(mut cache) => cache.remove(path),
+            Err(err) => log::info!("{:?}", err),
+        }
+    }
+
+    pub fn load(
+        &self,
+        path: &PathBuf,
+        mimetype: &mime::Mime,
+    ) -> ViewerResult<(mime::Mime, Arc<Vec<u8>>)> {
+        load_image(&self.cache, path, mimetype)
+    }
+}
+
+/*
+ * Test data structure:
+ * test_data/state/ +- a/ +- a/ +- a
+ *                  |     |     +- b
+ *                  |     |     +- c
+ *                  |     |
+ *                  |     +- b/ +- a
+ *                  |     |     +- b
+ *                  |     |     +- c
+ *                  |     +- c/
+ *                  |     +- d
+ *                  |
+ *                  +- b/ +- a/ +- a
+ *                  |     |
+ *                  |     +- b/ +- a
+ *                  |     |
+ *                  |     +- c/
+ *                  |
+ *                  +- c/ +- a
+ *                        +- b
+ *                        +- c
+ */
+#[test]
+fn test_get_neighbours() {
+    use std::collections::HashSet;
+
+    let extensions = HashSet::from([String::from("txt")]);
+    let mut state = ViewerState::new_text(&["test_data/state/a/b/b.txt"], extensions);
+    assert_eq!(state.set_root(&PathBuf::from("test_data/state")), Ok(()));
+    assert_eq!(state.reload_files(), Ok(()));
+
+    assert_eq!(
+        get_neighbours(&state, 2),
+        vec![
+            PathBuf::from("test_data/state/a/b/c.txt"),
+            PathBuf::from("test_data/state/a/b/a.txt"),
+            PathBuf::from("test_data/state/b/a/a.txt"),
+            PathBuf::from("test_data/state/a/a/c.txt"),
+        ]
+    );
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/a/b/b.txt")));
+}
+
+#[test]
+fn test_prefetcher_load() {
+    let prefetcher = Prefetcher::new(1, 1024);
+    let path = PathBuf::from("test_data/image/a.tga");
+    let mimetype: mime::Mime = "image/x-tga".parse().unwrap();
+
+    let (mimetype1, data1) = prefetcher.load(&path, &mimetype).unwrap();
+    assert_eq!(mimetype1, mime::IMAGE_PNG);
+    assert!(prefetcher.cache.lock().unwrap().contains(&path));
+
+    let (_, data2) = prefetcher.load(&path, &mimetype).unwrap();
+    assert!(Arc::ptr_eq(&data1, &data2));
+}
@@ -0,0 +1,41 @@
+use std::error::Error;
+
+use tauri::http::{Request, Response, ResponseBuilder};
+use tauri::{AppHandle, Manager};
+
+use super::error::ViewerError;
+use super::source::parse_token;
+use super::{PrefetcherManager, SourceRegistryManager};
+
+pub fn handle_viewer_protocol(
+    app: &AppHandle,
+    request: &Request,
+) -> Result<Response, Box<dyn Error>> {
+    let source = match parse_token(request.uri()) {
+        Some(token) => match app.state::<SourceRegistryManager>().0.lock() {
+            Ok(registry) => registry.get(token),
+            Err(_) => return Err(ViewerError::PoisonedLock.into()),
+        },
+        None => None,
+    };
+
+    match source {
+        Some((path, mimetype)) => match app.state::<PrefetcherManager>().0.load(&path, &mimetype) {
+            Ok((mimetype, data)) => {
+                log::debug!("Serve {:?} ({} bytes)", path, data.len());
+                Ok(ResponseBuilder::new()
+                    .mimetype(mimetype.as_ref())
+                    .status(200)
+                    .body(data.to_vec())?)
+            }
+            Err(err) => {
+                log::info!("{:?}", err);
+                Ok(ResponseBuilder::new().status(500).body(Vec::new())?)
+            }
+        },
+        None => {
+            log::info!("Unknown source requested: {:?}", request.uri());
+            Ok(ResponseBuilder::new().status(404).body(Vec::new())?)
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{create_dir_all, read_to_string, rename, write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ipc::{SortConfig, SpreadSettings, ViewSettings};
+use serde::{Deserialize, Serialize};
+
+use super::error::{ViewerError, ViewerResult};
+use super::state::ViewerState;
+
+// Same as the bundle identifier
+const APP_DIR: &str = "heptaliane.image-viewer";
+const SESSION_FILE: &str = "session.json";
+// Collections over this are forgotten from the least recently accessed
+const MAX_COLLECTIONS: usize = 100;
+
+// Reading position and settings of a traversal root
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CollectionSession {
+    pub path: PathBuf,
+    pub sort: SortConfig,
+    pub view: ViewSettings,
+    // Collections saved before the spread mode are shown one by one
+    #[serde(default)]
+    pub spread: SpreadSettings,
+    // Milliseconds since the unix epoch, which is 0 for collections saved before
+    #[serde(default)]
+    pub accessed: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    // Last viewed image
+    pub last: Option<PathBuf>,
+    #[serde(default)]
+    pub collections: HashMap<PathBuf, CollectionSession>,
+}
+
+impl Session {
+    pub fn record(&mut self, state: &ViewerState) {
+        // Nothing is recorded until something is opened
+        if let Ok(path) = state.get() {
+            let collection = CollectionSession {
+                path: path.clone(),
+                sort: state.sort(),
+                view: state.view(),
+                spread: state.spread(),
+                accessed: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_millis() as u64)
+                    .unwrap_or_default(),
+            };
+            self.collections
+                .insert(state.root().to_path_buf(), collection);
+            self.last = Some(path);
+            self.prune();
+        }
+    }
+
+    // Forgets collections which no longer exist, and the least recently accessed ones
+    fn prune(&mut self) {
+        self.collections.retain(|root, _| root.exists());
+        while self.collections.len() > MAX_COLLECTIONS {
+            let oldest = self
+                .collections
+                .iter()
+                .min_by_key(|(_, collection)| collection.accessed)
+                .map(|(root, _)| root.clone());
+            match oldest {
+                Some(root) => self.collections.remove(&root),
+                None => break,
+            };
+        }
+    }
+
+    // The file list is not updated until `reload_files` is called.
+    pub fn restore(&self, state: &mut ViewerState) {
+        if let Some(collection) = self.collections.get(state.root()) {
+            log::info!("Restore the session of {:?}", state.root());
+            state.set_sort(collection.sort);
+            state.set_view(collection.view);
+            state.set_spread(collection.spread);
+            if let Err(err) = state.set_current(&collection.path) {
+                log::info!("Failed to restore the position: {:?}", err);
+            }
+        }
+    }
+}
+
+// $XDG_STATE_HOME/<app>/session.json
+pub fn get_session_path() -> Option<PathBuf> {
+    let state_dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ if cfg!(unix) => tauri::api::path::home_dir().map(|home| home.join(".local/state")),
+        _ => tauri::api::path::local_data_dir(),
+    };
+    state_dir.map(|dir| dir.join(APP_DIR).join(SESSION_FILE))
+}
+
+pub fn load_session(path: &Path) -> ViewerResult<Session> {
+    match read_to_string(path) {
+        Ok(data) => serde_json::from_str(&data).map_err(ViewerError::decode),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn save_session(path: &Path, session: &Session) -> ViewerResult<()> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let data = match serde_json::to_string_pretty(session) {
+        Ok(data) => data,
+        Err(err) => return Err(ViewerError::internal(err)),
+    };
+
+    // The file is replaced at once not to be left broken
+    let mut temp_name = path.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    write(&temp_name, data)?;
+    rename(&temp_name, path)?;
+    Ok(())
+}
+
+#[test]
+fn test_session() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state").join(SESSION_FILE);
+    assert!(load_session(&path).is_err());
+
+    let session = Session {
+        last: Some(PathBuf::from("test_data/state/a/d.txt")),
+        ..Session::default()
+    };
+    assert_eq!(save_session(&path, &session), Ok(()));
+    assert_eq!(load_session(&path), Ok(session));
+    assert_eq!(dir.path().join("state").read_dir().unwrap().count(), 1);
+
+    // Sessions saved before collections were recorded
+    write(&path, r#"{"last": null}"#).unwrap();
+    assert_eq!(load_session(&path), Ok(Session::default()));
+}
+
+#[test]
+fn test_session_restore() {
+    use ipc::{FitMode, SortKey};
+    use std::collections::HashSet;
+
+    let extensions = HashSet::from([String::from("txt")]);
+    let mut state = ViewerState::new_text(&["test_data/state/a/b/a.txt"], extensions.clone());
+    assert_eq!(state.reload_files(), Ok(()));
+    assert!(state.next_cursor().is_ok());
+    state.set_view(ViewSettings {
+        fit: FitMode::Width,
+        zoom: 2.0,
+    });
+    let mut sort = state.sort();
+    sort.files.key = SortKey::Size;
+    state.set_sort(sort);
+    state.set_spread(SpreadSettings {
+        enabled: true,
+        ..SpreadSettings::default()
+    });
+
+    let mut session = Session::default();
+    session.record(&state);
+    assert_eq!(
+        session.last,
+        Some(PathBuf::from("test_data/state/a/b/b.txt"))
+    );
+
+    // Opening another file in the same root resumes the position
+    let mut state = ViewerState::new_text(&["test_data/state/a/b/c.txt"], extensions.clone());
+    session.restore(&mut state);
+    assert_eq!(state.reload_files(), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/a/b/b.txt")));
+    assert_eq!(state.sort().files.key, SortKey::Size);
+    assert_eq!(state.view().zoom, 2.0);
+    assert!(state.spread().enabled);
+
+    let mut state = ViewerState::new_text(&["test_data/state/a/a/c.txt"], extensions);
+    session.restore(&mut state);
+    assert_eq!(state.reload_files(), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/a/a/c.txt")));
+    assert_eq!(state.view(), ViewSettings::default());
+}
+
+#[test]
+fn test_session_prune() {
+    use std::collections::HashSet;
+    use std::fs::create_dir;
+
+    let dir = tempfile::tempdir().unwrap();
+    let collection = |path: &Path, accessed| CollectionSession {
+        path: path.join("a.txt"),
+        sort: SortConfig::default(),
+        view: ViewSettings::default(),
+        spread: SpreadSettings::default(),
+        accessed,
+    };
+    let mut session = Session::default();
+    for i in 0..MAX_COLLECTIONS {
+        let root = dir.path().join(i.to_string());
+        create_dir(&root).unwrap();
+        session
+            .collections
+            .insert(root.clone(), collection(&root, i as u64 + 1));
+    }
+    let removed = dir.path().join("removed");
+    session
+        .collections
+        .insert(removed.clone(), collection(&removed, u64::MAX));
+
+    let extensions = HashSet::from([String::from("txt")]);
+    let state = ViewerState::new_text(&["test_data/state/a/b/a.txt"], extensions);
+    session.record(&state);
+    assert_eq!(session.collections.len(), MAX_COLLECTIONS);
+    assert!(session.collections.contains_key(state.root()));
+    assert!(!session.collections.contains_key(&removed));
+    // The least recently accessed one is forgotten
+    assert!(!session.collections.contains_key(&dir.path().join("0")));
+    assert!(session.collections.contains_key(&dir.path().join("1")));
+}
@@ -0,0 +1,332 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use icu_collator::{Collator, CollatorOptions};
+use ipc::{SortChange, SortConfig, SortKey, SortMode, SortOrder, SortTarget};
+
+use super::exif_data::get_capture_date;
+use super::path::get_file_info;
+
+// Zeros of the Unicode decimal digit blocks which are commonly used in file names
+static DIGIT_ZEROS: [u32; 12] = [
+    0x0660, // Arabic-Indic
+    0x06F0, // Extended Arabic-Indic
+    0x0966, // Devanagari
+    0x09E6, // Bengali
+    0x0A66, // Gurmukhi
+    0x0AE6, // Gujarati
+    0x0BE6, // Tamil
+    0x0C66, // Telugu
+    0x0E50, // Thai
+    0x0ED0, // Lao
+    0x1040, // Myanmar
+    0xFF10, // Fullwidth
+];
+
+fn get_digit(c: char) -> Option<char> {
+    match c {
+        '0'..='9' => Some(c),
+        _ if c.is_numeric() => DIGIT_ZEROS
+            .iter()
+            .find(|zero| (**zero..**zero + 10).contains(&(c as u32)))
+            .and_then(|zero| char::from_digit(c as u32 - zero, 10)),
+        _ => None,
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
+enum NaturalChunk {
+    // Digit runs are compared by their length without leading zeros, then by digits
+    Number(usize, String),
+    Text(String),
+}
+
+// Names are compared by their case-folded chunks first. Leading zeros and letter cases
+// only break ties, so that "a01b2" comes after "a1b1" and "Page2" before "page10".
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
+pub struct NaturalKey {
+    chunks: Vec<NaturalChunk>,
+    leading_zeros: Vec<usize>,
+    name: String,
+}
+
+pub fn get_natural_key(name: &str) -> NaturalKey {
+    let mut chunks = vec![];
+    let mut leading_zeros = vec![];
+    let mut chars = name.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let is_digit = get_digit(c).is_some();
+        let mut chunk = String::new();
+        while let Some(&c) = chars.peek() {
+            match get_digit(c) {
+                Some(digit) if is_digit => chunk.push(digit),
+                None if !is_digit => chunk.extend(c.to_lowercase()),
+                _ => break,
+            }
+            chars.next();
+        }
+        chunks.push(match is_digit {
+            true => {
+                let digits = chunk.trim_start_matches('0').to_string();
+                leading_zeros.push(chunk.len() - digits.len());
+                NaturalChunk::Number(digits.len(), digits)
+            }
+            false => NaturalChunk::Text(chunk),
+        });
+    }
+    NaturalKey {
+        chunks,
+        leading_zeros,
+        name: name.to_string(),
+    }
+}
+
+// Unknown locales fall back to the root collation
+fn create_collator(locale: Option<String>) -> Collator {
+    let locale = locale
+        .and_then(|locale| locale.parse().ok())
+        .unwrap_or_default();
+    match Collator::try_new(&locale, CollatorOptions::new()) {
+        Ok(collator) => collator,
+        Err(err) => {
+            log::warn!("{:?}", err);
+            Collator::try_new(&Default::default(), CollatorOptions::new()).unwrap()
+        }
+    }
+}
+
+fn get_collator() -> &'static Collator {
+    static COLLATOR: OnceLock<Collator> = OnceLock::new();
+    COLLATOR.get_or_init(|| create_collator(sys_locale::get_locale()))
+}
+
+// Names are collated by the system locale, where letter cases come after accents in
+// breaking ties. Names equal to the collator are ordered by their code points.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CollationKey(String);
+
+impl Ord for CollationKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        get_collator()
+            .compare(&self.0, &other.0)
+            .then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for CollationKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
+enum SortValue {
+    // Compared by the path only
+    Name,
+    Natural(NaturalKey),
+    Collated(CollationKey),
+    Time(Option<SystemTime>),
+    Size(Option<u64>),
+    Date(Option<String>),
+    Random(u64),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SortElem {
+    value: SortValue,
+    path: PathBuf,
+    order: SortOrder,
+}
+
+impl Ord for SortElem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Paths break ties, so that the order is stable for equal values
+        let ordering = self
+            .value
+            .cmp(&other.value)
+            .then_with(|| self.path.cmp(&other.path));
+        match self.order {
+            SortOrder::Ascending => ordering,
+            SortOrder::Descending => ordering.reverse(),
+        }
+    }
+}
+
+impl PartialOrd for SortElem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn get_file_name(path: &PathBuf) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+pub fn get_sort_elem(path: &PathBuf, mode: SortMode, seed: u64) -> SortElem {
+    let value = match mode.key {
+        SortKey::Name => SortValue::Name,
+        SortKey::Natural => SortValue::Natural(get_natural_key(&get_file_name(path))),
+        SortKey::CaseInsensitive => SortValue::Collated(CollationKey(get_file_name(path))),
+        SortKey::Modified => {
+            SortValue::Time(get_file_info(path).ok().and_then(|info| info.modified))
+        }
+        SortKey::Created => SortValue::Time(get_file_info(path).ok().and_then(|info| info.created)),
+        SortKey::Size => SortValue::Size(get_file_info(path).ok().map(|info| info.size)),
+        SortKey::ExifDate => SortValue::Date(get_capture_date(path).ok()),
+        SortKey::Random => {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            path.hash(&mut hasher);
+            SortValue::Random(hasher.finish())
+        }
+    };
+    SortElem {
+        value,
+        path: path.clone(),
+        order: mode.order,
+    }
+}
+
+pub fn apply_sort_change(sort: SortConfig, target: SortTarget, change: SortChange) -> SortConfig {
+    let mode = match target {
+        SortTarget::Files => sort.files,
+        SortTarget::Directories => sort.directories,
+    };
+    let mode = match change {
+        SortChange::Set(mode) => mode,
+        SortChange::NextKey => SortMode {
+            key: mode.key.next(),
+            ..mode
+        },
+        SortChange::Reverse => mode.reversed(),
+    };
+    match target {
+        SortTarget::Files => SortConfig {
+            files: mode,
+            ..sort
+        },
+        SortTarget::Directories => SortConfig {
+            directories: mode,
+            ..sort
+        },
+    }
+}
+
+#[test]
+fn test_natural_key() {
+    let sort = |names: &[&'static str]| {
+        let mut names = names.to_vec();
+        names.sort_by_cached_key(|name| get_natural_key(name));
+        names
+    };
+
+    assert_eq!(
+        sort(&["page10.png", "page2.png", "page1.png"]),
+        vec!["page1.png", "page2.png", "page10.png"]
+    );
+    assert_eq!(
+        sort(&["a02", "a2", "a1b2", "a01b1"]),
+        vec!["a01b1", "a1b2", "a2", "a02"]
+    );
+    assert_eq!(
+        sort(&["page10", "Page2", "page2", "PAGE1"]),
+        vec!["PAGE1", "Page2", "page2", "page10"]
+    );
+    assert_eq!(
+        sort(&["第１０話", "第9話", "第２話"]),
+        vec!["第２話", "第9話", "第１０話"]
+    );
+    assert_eq!(
+        sort(&["12345678901234567890123", "9", "x"]),
+        vec!["9", "12345678901234567890123", "x"]
+    );
+}
+
+#[test]
+fn test_collation_key() {
+    let sort = |names: &[&'static str]| {
+        let mut names = names.to_vec();
+        names.sort_by_cached_key(|name| CollationKey(name.to_string()));
+        names
+    };
+
+    // Accented letters are put beside the plain ones, unlike their code points
+    assert_eq!(
+        sort(&["zebra", "Écran", "eclair", "ecran"]),
+        vec!["eclair", "ecran", "Écran", "zebra"]
+    );
+    assert_eq!(sort(&["B", "a", "b", "A"]), vec!["a", "A", "b", "B"]);
+
+    // Swedish puts "ä" after "z"
+    let root = create_collator(None);
+    let swedish = create_collator(Some("sv-SE".to_string()));
+    assert_eq!(root.compare("ä", "z"), Ordering::Less);
+    assert_eq!(swedish.compare("ä", "z"), Ordering::Greater);
+    assert_eq!(
+        create_collator(Some("invalid locale".to_string())).compare("ä", "z"),
+        Ordering::Less
+    );
+}
+
+#[test]
+fn test_sort_elem() {
+    let sort = |names: &[&str], key: SortKey, order: SortOrder| {
+        let mut paths: Vec<PathBuf> = names
+            .iter()
+            .map(|name| PathBuf::from("test_data/sort").join(name))
+            .collect();
+        paths.sort_by_cached_key(|path| get_sort_elem(path, SortMode { key, order }, 0));
+        paths
+            .iter()
+            .map(|path| get_file_name(path))
+            .collect::<Vec<String>>()
+    };
+    let names = ["img1.txt", "img10.txt", "img2.txt", "Img3.txt"];
+
+    assert_eq!(
+        sort(&names, SortKey::Name, SortOrder::Ascending),
+        vec!["Img3.txt", "img1.txt", "img10.txt", "img2.txt"]
+    );
+    assert_eq!(
+        sort(&names, SortKey::Natural, SortOrder::Ascending),
+        vec!["img1.txt", "img2.txt", "Img3.txt", "img10.txt"]
+    );
+    assert_eq!(
+        sort(&names, SortKey::CaseInsensitive, SortOrder::Ascending),
+        vec!["img1.txt", "img10.txt", "img2.txt", "Img3.txt"]
+    );
+    assert_eq!(
+        sort(&names, SortKey::Size, SortOrder::Descending),
+        vec!["Img3.txt", "img1.txt", "img10.txt", "img2.txt"]
+    );
+    assert_eq!(
+        sort(
+            &["exif/a.jpg", "exif/b.jpg", "exif/c.jpg"],
+            SortKey::ExifDate,
+            SortOrder::Ascending
+        ),
+        vec!["c.jpg", "b.jpg", "a.jpg"]
+    );
+
+    let random = sort(&names, SortKey::Random, SortOrder::Ascending);
+    assert_eq!(random, sort(&names, SortKey::Random, SortOrder::Ascending));
+}
+
+#[test]
+fn test_apply_sort_change() {
+    let sort = SortConfig::default();
+
+    let reversed = apply_sort_change(sort, SortTarget::Files, SortChange::Reverse);
+    assert_eq!(reversed.files.order, SortOrder::Descending);
+    assert_eq!(reversed.directories, sort.directories);
+
+    let next = apply_sort_change(reversed, SortTarget::Directories, SortChange::NextKey);
+    assert_eq!(next.files, reversed.files);
+    assert_eq!(next.directories.key, sort.directories.key.next());
+}
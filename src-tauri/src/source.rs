@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use mime;
+
+pub const PROTOCOL: &str = "viewer";
+const MAX_SOURCES: usize = 16;
+
+#[derive(Default)]
+pub struct SourceRegistry {
+    sources: VecDeque<(u64, PathBuf, mime::Mime)>,
+    counter: u64,
+}
+
+impl SourceRegistry {
+    pub fn register(&mut self, path: &PathBuf, mimetype: &mime::Mime) -> u64 {
+        self.counter += 1;
+        self.sources
+            .push_back((self.counter, path.clone(), mimetype.clone()));
+        while self.sources.len() > MAX_SOURCES {
+            self.sources.pop_front();
+        }
+        self.counter
+    }
+
+    pub fn get(&self, token: u64) -> Option<(PathBuf, mime::Mime)> {
+        self.sources
+            .iter()
+            .find(|(t, _, _)| *t == token)
+            .map(|(_, path, mimetype)| (path.clone(), mimetype.clone()))
+    }
+}
+
+pub fn source_url(token: u64) -> String {
+    // Windows webview exposes custom protocols as https://<scheme>.localhost
+    match cfg!(windows) {
+        true => format!("https://{}.localhost/current/{}", PROTOCOL, token),
+        false => format!("{}://localhost/current/{}", PROTOCOL, token),
+    }
+}
+
+pub fn parse_token(uri: &str) -> Option<u64> {
+    uri.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .and_then(|token| token.parse::<u64>().ok())
+}
+
+#[test]
+fn test_source_registry() {
+    let mut registry = SourceRegistry::default();
+    let path = PathBuf::from("test_data/state/a/d.txt");
+
+    let token = registry.register(&path, &mime::IMAGE_PNG);
+    assert_eq!(registry.get(token), Some((path.clone(), mime::IMAGE_PNG)));
+    assert_eq!(registry.get(token + 1), None);
+
+    for _ in 0..MAX_SOURCES {
+        registry.register(&path, &mime::IMAGE_PNG);
+    }
+    assert_eq!(registry.get(token), None);
+}
+
+#[test]
+fn test_parse_token() {
+    assert_eq!(parse_token(&source_url(12)), Some(12));
+    assert_eq!(parse_token("viewer://localhost/current/3/"), Some(3));
+    assert_eq!(parse_token("viewer://localhost/current/"), None);
+}
@@ -1,126 +1,620 @@
-use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-use super::path::{get_child_files, next_directory, prev_directory};
+use ipc::{SortConfig, SpreadSettings, ViewSettings, Wrap};
 
-fn sort_by_path(path: &PathBuf) -> PathBuf {
-    path.clone()
-}
+use super::detect::{DetectPolicy, ImageDetector};
+use super::error::{ViewerError, ViewerResult};
+use super::image::get_oriented_dimensions;
+use super::index::CollectionIndex;
+use super::path::{
+    get_child_directories, get_child_files, is_directory, is_file, last_descendant, next_directory,
+    prev_directory,
+};
+use super::sort::{get_sort_elem, SortElem};
+
+// Images whose page kind is remembered, which are forgotten at once when exceeded
+const MAX_SINGLE_PAGES: usize = 4096;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ViewerState {
     paths: Vec<PathBuf>,
     cursor: usize,
-    extensions: HashSet<String>,
+    detector: ImageDetector,
+    sort: SortConfig,
+    // Directory navigation never leaves the current root, and continues to the next root at
+    // its ends. A file root is a collection of the single file.
+    roots: Vec<PathBuf>,
+    // Files opened together in each root, which is empty to show the whole root
+    selections: Vec<Vec<PathBuf>>,
+    root_index: usize,
+    // Whether to move to the other end at the ends of the collection
+    wrap: bool,
+    wrapped: Option<Wrap>,
+    // Whether to index all images under the root as one sequence
+    recursive: bool,
+    index: Option<CollectionIndex>,
+    // Only kept to be saved with the reading position
+    view: ViewSettings,
+    spread: SpreadSettings,
+    // Whether each image is shown alone in the spread mode, shared by the snapshots
+    single_pages: Arc<Mutex<HashMap<PathBuf, bool>>>,
 }
 
 impl ViewerState {
-    pub fn new(filename: &str, extensions: HashSet<String>) -> Self {
-        log::debug!("Initialized state with {:?}", filename);
+    pub fn new<P: AsRef<Path>>(paths: &[P], extensions: HashSet<String>) -> Self {
         log::info!("Available extensions: {:?}", extensions);
+        Self::with_detector(
+            paths,
+            ImageDetector::new(DetectPolicy::default(), extensions),
+        )
+    }
 
+    // The text fixtures have no image contents, so only their extensions are checked
+    #[cfg(test)]
+    pub fn new_text<P: AsRef<Path>>(paths: &[P], extensions: HashSet<String>) -> Self {
+        Self::with_detector(
+            paths,
+            ImageDetector::new(DetectPolicy::Extension, extensions),
+        )
+    }
+
+    // Opens another collection with the same settings
+    pub fn reopen<P: AsRef<Path>>(&self, paths: &[P]) -> Self {
         Self {
-            paths: vec![Path::new(filename).to_path_buf()],
+            sort: self.sort,
+            wrap: self.wrap,
+            recursive: self.recursive,
+            view: self.view,
+            spread: self.spread,
+            single_pages: self.single_pages.clone(),
+            ..Self::with_detector(paths, self.detector.clone())
+        }
+    }
+
+    fn with_detector<P: AsRef<Path>>(paths: &[P], detector: ImageDetector) -> Self {
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect();
+        log::debug!("Initialized state with {:?}", paths);
+
+        let (roots, selections) = match paths.as_slice() {
+            [] => (vec![PathBuf::new()], vec![vec![]]),
+            // A single file is opened with the other files in its directory
+            [path] if !is_directory(path) => (
+                vec![path.parent().unwrap_or(path).to_path_buf()],
+                vec![vec![]],
+            ),
+            // Opened directories and archives are the root by themselves
+            _ => group_files(&paths),
+        };
+        Self {
+            paths: paths.into_iter().take(1).collect(),
             cursor: 0,
-            extensions,
+            detector,
+            sort: SortConfig::default(),
+            roots,
+            selections,
+            root_index: 0,
+            wrap: false,
+            wrapped: None,
+            recursive: false,
+            index: None,
+            view: ViewSettings::default(),
+            spread: SpreadSettings::default(),
+            single_pages: Arc::default(),
         }
     }
 
-    pub fn reload_files(&mut self) -> Result<(), String> {
+    pub fn root(&self) -> &Path {
+        &self.roots[self.root_index]
+    }
+
+    // Replaces the current root
+    pub fn set_root(&mut self, root: &Path) -> ViewerResult<()> {
         match self.get() {
-            Ok(current) => match current.parent() {
-                Some(parent) => match get_child_files(parent, &self.extensions, &sort_by_path) {
+            Ok(current) if current.starts_with(root) => {
+                log::info!("Traversal root: {:?}", root);
+                self.roots[self.root_index] = root.to_path_buf();
+                self.selections[self.root_index].clear();
+                Ok(())
+            }
+            Ok(current) => Err(ViewerError::InvalidArgument(format!(
+                "{:?} is not in {:?}",
+                current, root
+            ))),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn set_detect_policy(&mut self, policy: DetectPolicy) {
+        log::info!("Detect policy: {:?}", policy);
+        self.detector.set_policy(policy);
+    }
+
+    pub fn detector(&self) -> &ImageDetector {
+        &self.detector
+    }
+
+    pub fn sort(&self) -> SortConfig {
+        self.sort
+    }
+
+    // The file list is not reordered until `reload_files` is called.
+    pub fn set_sort(&mut self, sort: SortConfig) {
+        log::info!("Sort: {:?}", sort);
+        self.sort = sort;
+    }
+
+    fn file_sort(&self) -> impl Fn(&PathBuf) -> SortElem {
+        let sort = self.sort;
+        move |path| get_sort_elem(path, sort.files, sort.seed)
+    }
+
+    fn directory_sort(&self) -> impl Fn(&PathBuf) -> SortElem {
+        let sort = self.sort;
+        move |path| get_sort_elem(path, sort.directories, sort.seed)
+    }
+
+    pub fn view(&self) -> ViewSettings {
+        self.view
+    }
+
+    pub fn set_view(&mut self, view: ViewSettings) {
+        self.view = view;
+    }
+
+    pub fn spread(&self) -> SpreadSettings {
+        self.spread
+    }
+
+    // The cursor is not aligned to a spread until `align_spread` is called.
+    pub fn set_spread(&mut self, spread: SpreadSettings) {
+        log::info!("Spread: {:?}", spread);
+        self.spread = spread;
+    }
+
+    // Wide images are shown alone, and so are the images whose size is unknown
+    fn is_single_page(&self, cursor: usize) -> bool {
+        let path = match self.paths.get(cursor) {
+            Some(path) => path,
+            None => return true,
+        };
+        if let Ok(pages) = self.single_pages.lock() {
+            if let Some(single) = pages.get(path) {
+                return *single;
+            }
+        }
+
+        let single = match self.detector.detect(path) {
+            Ok(mimetype) => match get_oriented_dimensions(path, &mimetype) {
+                Ok((width, height)) => width > height,
+                Err(_) => true,
+            },
+            Err(_) => true,
+        };
+        if let Ok(mut pages) = self.single_pages.lock() {
+            if pages.len() >= MAX_SINGLE_PAGES {
+                pages.clear();
+            }
+            pages.insert(path.clone(), single);
+        }
+        single
+    }
+
+    // Called when the size of the image as displayed may have been changed
+    pub fn forget_page(&self, path: &Path) {
+        if let Ok(mut pages) = self.single_pages.lock() {
+            pages.remove(path);
+        }
+    }
+
+    // Number of images in the spread starting at `start`
+    fn spread_len_at(&self, start: usize) -> usize {
+        let spread = self.spread;
+        match spread.enabled
+            && !(spread.cover && start == 0)
+            && start + 1 < self.paths.len()
+            && !self.is_single_page(start)
+            && !self.is_single_page(start + 1)
+        {
+            true => 2,
+            false => 1,
+        }
+    }
+
+    // Number of images shown from the cursor, which is 2 for a spread
+    pub fn spread_len(&self) -> usize {
+        self.spread_len_at(self.cursor)
+    }
+
+    // Moves the cursor to the first image of its spread. Spreads are paired from the start of
+    // the directory, so that moving back shows the same spreads as moving forward.
+    pub fn align_spread(&mut self) {
+        let mut start = 0;
+        while start < self.cursor {
+            match start + self.spread_len_at(start) {
+                next if next > self.cursor => break,
+                next => start = next,
+            }
+        }
+        self.cursor = start;
+    }
+
+    // Image shown beside the current one, if the cursor is on a spread
+    pub fn facing(&self) -> Option<PathBuf> {
+        match self.spread_len() {
+            2 => self.paths.get(self.cursor + 1).cloned(),
+            _ => None,
+        }
+    }
+
+    // Moves to `path` in the current root. The file list is not updated until
+    // `reload_files` is called.
+    pub fn set_current(&mut self, path: &Path) -> ViewerResult<()> {
+        match path.starts_with(self.root()) && is_file(path) {
+            true => {
+                self.paths = vec![path.to_path_buf()];
+                self.cursor = 0;
+                Ok(())
+            }
+            false => Err(ViewerError::NotFound(path.to_path_buf())),
+        }
+    }
+
+    pub fn set_wrap(&mut self, wrap: bool) {
+        log::info!("Wrap around: {:?}", wrap);
+        self.wrap = wrap;
+    }
+
+    // Where the moves since the last `clear_wrapped` call have wrapped around to
+    pub fn wrapped(&self) -> Option<Wrap> {
+        self.wrapped
+    }
+
+    pub fn clear_wrapped(&mut self) {
+        self.wrapped = None;
+    }
+
+    // The index is not built until `reload_files` is called.
+    pub fn set_recursive(&mut self, recursive: bool) {
+        log::info!("Recursive: {:?}", recursive);
+        self.recursive = recursive;
+    }
+
+    // Rebuilds the index when the root or the order has been changed
+    fn update_index(&mut self) {
+        let root = self.root().to_path_buf();
+        self.index = match self.index.take() {
+            _ if !self.recursive || !self.is_walkable() => None,
+            Some(index) if index.is_built_for(&root, self.sort) => Some(index),
+            _ => Some(CollectionIndex::build(&root, &self.detector, self.sort)),
+        };
+    }
+
+    // Position in the whole root, if it has been indexed
+    pub fn position(&self) -> Option<usize> {
+        match (&self.index, self.get()) {
+            (Some(index), Ok(path)) => index.position(&path),
+            _ => None,
+        }
+    }
+
+    pub fn total(&self) -> Option<usize> {
+        self.index.as_ref().and_then(CollectionIndex::total)
+    }
+
+    // Moves to the `position`-th image in the whole root if it is indexed, or in the current
+    // directory otherwise
+    pub fn jump_to(&mut self, position: usize) -> ViewerResult<()> {
+        let path = match &self.index {
+            Some(index) => match index.get(position) {
+                Some(path) => path,
+                None if !index.is_complete() => {
+                    return Err(ViewerError::Indexing(self.root().to_path_buf()))
+                }
+                None => {
+                    return Err(ViewerError::InvalidArgument(format!(
+                        "{:?} is out of {:?} images",
+                        position,
+                        index.len()
+                    )))
+                }
+            },
+            None if position < self.paths.len() => {
+                self.cursor = position;
+                return Ok(());
+            }
+            None => {
+                return Err(ViewerError::InvalidArgument(format!(
+                    "{:?} is out of {:?} images",
+                    position,
+                    self.paths.len()
+                )))
+            }
+        };
+        match self.set_current(&path) {
+            Ok(_) => self.reload_files(),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Moves to `percent`% of the root, which needs to be indexed to the end, or of the
+    // current directory
+    pub fn seek(&mut self, percent: f64) -> ViewerResult<()> {
+        let total = match &self.index {
+            Some(index) => match index.total() {
+                Some(total) => total,
+                None => return Err(ViewerError::Indexing(self.root().to_path_buf())),
+            },
+            None => self.paths.len(),
+        };
+        match total {
+            0 => Err(ViewerError::NoMoreImages),
+            total => {
+                let position = (total as f64 * percent.clamp(0.0, 100.0) / 100.0) as usize;
+                self.jump_to(position.min(total - 1))
+            }
+        }
+    }
+
+    // Position of `current` in the sorted `paths`, or of the next file if it has been removed
+    fn find_cursor(&self, paths: &[PathBuf], current: &PathBuf) -> usize {
+        if let Some(cursor) = paths.iter().position(|path| path == current) {
+            return cursor;
+        }
+        // Only a few sort keys are computed again, as they may read the files
+        let file_sort = self.file_sort();
+        let current_elem = file_sort(current);
+        match paths.partition_point(|path| file_sort(path) < current_elem) {
+            cursor if cursor < paths.len() => cursor,
+            _ => 0,
+        }
+    }
+
+    pub fn reload_files(&mut self) -> ViewerResult<()> {
+        match self.get() {
+            Ok(current) => match self.parent_dir() {
+                Ok(parent) => match self.list_files(&parent) {
                     Ok(paths) if paths.len() > 0 => {
+                        self.cursor = match parent == current {
+                            true => 0,
+                            false => self.find_cursor(&paths, &current),
+                        };
                         self.paths = paths;
-                        self.cursor = self
-                            .paths
-                            .iter()
-                            .position(|path| path.partial_cmp(&current).unwrap() != Ordering::Less)
-                            .unwrap_or(0);
                         log::debug!(
                             "File list updated ({:?} files, cursor = {:?})",
                             self.paths.len(),
                             self.cursor
                         );
+                        self.update_index();
                         Ok(())
                     }
                     Err(err) => Err(err),
+                    _ if parent == current => self.next_directory(),
                     _ => self.prev_directory(),
                 },
-                None => Err("Parent directory is not found.".to_string()),
+                Err(err) => Err(err),
             },
             Err(err) => Err(err),
         }
     }
 
-    pub fn get(&self) -> Result<PathBuf, String> {
+    pub fn get(&self) -> ViewerResult<PathBuf> {
         match self.paths.get(self.cursor) {
             Some(path) => Ok(path.clone()),
-            _ => Err(String::from("Files are not in buffer.")),
+            _ if self.paths.is_empty() => Err(ViewerError::NothingOpened),
+            _ => Err(ViewerError::NoMoreImages),
         }
     }
 
-    fn parent_dir(&self) -> Result<PathBuf, String> {
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn count(&self) -> usize {
+        self.paths.len()
+    }
+
+    fn parent_dir(&self) -> ViewerResult<PathBuf> {
         match self.get() {
+            // Opened directories and archives are listed by themselves
+            Ok(filename) if is_directory(&filename) => Ok(filename),
             Ok(filename) => match filename.parent() {
                 Some(parent) => Ok(parent.to_path_buf()),
-                _ => Err(String::from("No parent directory is found.")),
+                _ => Err(ViewerError::RootReached(filename)),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    // Lists child directories and images, as the directory walk sees them
+    pub fn list_directory(&self, dir: &Path) -> ViewerResult<(Vec<PathBuf>, Vec<PathBuf>)> {
+        match get_child_directories(dir, &self.directory_sort()) {
+            Ok(directories) => match get_child_files(dir, &self.detector, &self.file_sort()) {
+                Ok(files) => Ok((directories, files)),
+                Err(err) => Err(err),
             },
-            Err(err) => Err(format!("{:?}", err)),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Whether the directories under the current root are walked
+    fn is_walkable(&self) -> bool {
+        is_directory(self.root()) && self.selections[self.root_index].is_empty()
+    }
+
+    fn list_files(&self, dir: &Path) -> ViewerResult<Vec<PathBuf>> {
+        let root = self.root().to_path_buf();
+        let selection = &self.selections[self.root_index];
+        match is_directory(&root) {
+            true if !selection.is_empty() => {
+                let mut paths: Vec<PathBuf> = selection
+                    .iter()
+                    .filter(|path| self.detector.is_image(path))
+                    .cloned()
+                    .collect();
+                paths.sort_by_cached_key(self.file_sort());
+                Ok(paths)
+            }
+            true => get_child_files(dir, &self.detector, &self.file_sort()),
+            false => Ok(match self.detector.is_image(&root) {
+                true => vec![root],
+                false => vec![],
+            }),
         }
     }
 
-    fn change_directory<F>(&mut self, modifier: F) -> Result<(), String>
+    // Replaces the file list with the images in `dir`, if any
+    fn load_directory(&mut self, dir: &Path) -> ViewerResult<bool> {
+        match self.list_files(dir) {
+            Ok(paths) if paths.len() > 0 => {
+                self.paths = paths;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn change_directory<F>(&mut self, start: PathBuf, modifier: F) -> ViewerResult<()>
     where
         F: Fn(&PathBuf) -> Option<PathBuf>,
     {
-        match self.parent_dir() {
-            Ok(parent) => {
-                let mut current = parent.clone();
-                loop {
-                    log::debug!("Changing directory (current target: {:?})", current);
-                    match modifier(&current) {
-                        Some(dirname) => {
-                            current = PathBuf::from(&dirname);
-                            match get_child_files(&current, &self.extensions, &sort_by_path) {
-                                Ok(paths) if paths.len() > 0 => {
-                                    self.paths = paths;
-                                    return Ok(());
-                                }
-                                Err(err) => return Err(err),
-                                _ => (),
-                            }
-                        }
-                        None => return Err(format!("(current: {:?})", parent)),
+        let mut current = start;
+        loop {
+            log::debug!("Changing directory (current target: {:?})", current);
+            match modifier(&current) {
+                Some(dirname) => {
+                    current = dirname;
+                    match self.load_directory(&current) {
+                        Ok(true) => return Ok(()),
+                        Ok(false) => (),
+                        Err(err) => return Err(err),
                     }
                 }
+                None => return Err(ViewerError::EndOfCollection(self.root().to_path_buf())),
+            }
+        }
+    }
+
+    // Loads the first directory with images in the current root
+    fn load_first(&mut self) -> ViewerResult<()> {
+        let directory_sort = self.directory_sort();
+        let root = self.root().to_path_buf();
+        match self.load_directory(&root) {
+            Ok(true) => Ok(()),
+            Ok(false) if self.is_walkable() => {
+                self.change_directory(root.clone(), |p| next_directory(p, &root, &directory_sort))
+            }
+            Ok(false) => Err(ViewerError::EndOfCollection(root)),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Loads the last directory with images in the current root
+    fn load_last(&mut self) -> ViewerResult<()> {
+        let directory_sort = self.directory_sort();
+        let root = self.root().to_path_buf();
+        let last = match self.is_walkable() {
+            true => last_descendant(root.clone(), &directory_sort),
+            false => root.clone(),
+        };
+        match self.load_directory(&last) {
+            Ok(true) => Ok(()),
+            Ok(false) if self.is_walkable() => {
+                self.change_directory(last, |p| prev_directory(p, &root, &directory_sort))
+            }
+            Ok(false) => Err(ViewerError::EndOfCollection(root)),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Moves to the next root with images, skipping roots without images
+    fn change_root<F>(&mut self, forward: bool, load: F) -> ViewerResult<()>
+    where
+        F: Fn(&mut Self) -> ViewerResult<()>,
+    {
+        let start = self.root_index;
+        let mut result = Err(ViewerError::EndOfCollection(self.root().to_path_buf()));
+        for _ in 0..self.roots.len() {
+            let last_index = self.roots.len() - 1;
+            match (forward, self.root_index) {
+                (true, index) if index < last_index => self.root_index += 1,
+                (false, index) if index > 0 => self.root_index -= 1,
+                _ if !self.wrap => break,
+                (true, _) => {
+                    self.root_index = 0;
+                    self.wrapped = Some(Wrap::ToFirst);
+                }
+                (false, _) => {
+                    self.root_index = last_index;
+                    self.wrapped = Some(Wrap::ToLast);
+                }
             }
-            Err(err) => return Err(err),
+            result = load(self);
+            if !matches!(result, Err(ViewerError::EndOfCollection(_))) {
+                break;
+            }
+        }
+        if result.is_err() {
+            self.root_index = start;
         }
+        result
     }
 
-    pub fn next_directory(&mut self) -> Result<(), String> {
-        match self.change_directory(|p| next_directory(p, &sort_by_path)) {
+    pub fn next_directory(&mut self) -> ViewerResult<()> {
+        let directory_sort = self.directory_sort();
+        let root = self.root().to_path_buf();
+        let result = match self.parent_dir() {
+            // File roots have no directories to walk
+            Ok(_) if !self.is_walkable() => Err(ViewerError::EndOfCollection(root.clone())),
+            Ok(parent) => {
+                self.change_directory(parent, |p| next_directory(p, &root, &directory_sort))
+            }
+            Err(err) => Err(err),
+        };
+        let result = match result {
+            Err(ViewerError::EndOfCollection(_)) => self.change_root(true, Self::load_first),
+            result => result,
+        };
+        match result {
             Ok(_) => {
                 self.move_first();
+                self.update_index();
                 Ok(())
             }
             Err(err) => Err(err),
         }
     }
 
-    pub fn prev_directory(&mut self) -> Result<(), String> {
-        match self.change_directory(|p| prev_directory(p, &sort_by_path)) {
+    pub fn prev_directory(&mut self) -> ViewerResult<()> {
+        let directory_sort = self.directory_sort();
+        let root = self.root().to_path_buf();
+        let result = match self.parent_dir() {
+            Ok(_) if !self.is_walkable() => Err(ViewerError::EndOfCollection(root.clone())),
+            Ok(parent) => {
+                self.change_directory(parent, |p| prev_directory(p, &root, &directory_sort))
+            }
+            Err(err) => Err(err),
+        };
+        let result = match result {
+            Err(ViewerError::EndOfCollection(_)) => self.change_root(false, Self::load_last),
+            result => result,
+        };
+        match result {
             Ok(_) => {
                 self.move_last();
+                self.update_index();
                 Ok(())
             }
             Err(err) => Err(err),
         }
     }
 
-    pub fn next_cursor(&mut self) -> Result<(), String> {
+    pub fn next_cursor(&mut self) -> ViewerResult<()> {
         match self.cursor + 1 < self.paths.len() {
             true => {
                 self.cursor += 1;
@@ -130,7 +624,7 @@ impl ViewerState {
         }
     }
 
-    pub fn prev_cursor(&mut self) -> Result<(), String> {
+    pub fn prev_cursor(&mut self) -> ViewerResult<()> {
         match self.cursor > 0 {
             true => {
                 self.cursor -= 1;
@@ -140,15 +634,110 @@ impl ViewerState {
         }
     }
 
+    // Moves past the spread shown from the cursor
+    pub fn next_page(&mut self) -> ViewerResult<()> {
+        match self.spread_len() {
+            2 => {
+                self.cursor += 1;
+                let result = self.next_cursor();
+                if result.is_err() {
+                    self.cursor -= 1;
+                }
+                result
+            }
+            _ => self.next_cursor(),
+        }
+    }
+
+    // Moves to the first image of the previous spread
+    pub fn prev_page(&mut self) -> ViewerResult<()> {
+        match self.prev_cursor() {
+            Ok(_) => {
+                self.align_spread();
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     pub fn move_first(&mut self) {
         self.cursor = 0;
     }
 
     pub fn move_last(&mut self) {
-        self.cursor = self.paths.len() - 1;
+        self.cursor = self.paths.len().saturating_sub(1);
+    }
+
+    // Moves to the first image of the first root with images
+    pub fn move_collection_first(&mut self) -> ViewerResult<()> {
+        let start = self.root_index;
+        self.root_index = 0;
+        let result = match self.load_first() {
+            Err(ViewerError::EndOfCollection(_)) => self.change_root(true, Self::load_first),
+            result => result,
+        };
+        match result {
+            Ok(_) => {
+                self.move_first();
+                self.update_index();
+                Ok(())
+            }
+            Err(err) => {
+                self.root_index = start;
+                Err(err)
+            }
+        }
+    }
+
+    // Moves to the last image of the last root with images
+    pub fn move_collection_last(&mut self) -> ViewerResult<()> {
+        let start = self.root_index;
+        self.root_index = self.roots.len() - 1;
+        let result = match self.load_last() {
+            Err(ViewerError::EndOfCollection(_)) => self.change_root(false, Self::load_last),
+            result => result,
+        };
+        match result {
+            Ok(_) => {
+                self.move_last();
+                self.update_index();
+                Ok(())
+            }
+            Err(err) => {
+                self.root_index = start;
+                Err(err)
+            }
+        }
     }
 }
 
+// Consecutive files in the same directory are traversed together in their directory.
+// Returns the roots and the files opened in each of them.
+fn group_files(paths: &[PathBuf]) -> (Vec<PathBuf>, Vec<Vec<PathBuf>>) {
+    let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = vec![];
+    for path in paths {
+        let parent = match path.parent() {
+            Some(parent) if !is_directory(path) => parent,
+            _ => {
+                groups.push((path.clone(), vec![]));
+                continue;
+            }
+        };
+        match groups.last_mut() {
+            Some((root, files)) if !files.is_empty() && root == parent => files.push(path.clone()),
+            _ => groups.push((parent.to_path_buf(), vec![path.clone()])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(root, files)| match files.as_slice() {
+            // A file alone is the root by itself
+            [file] => (file.clone(), vec![]),
+            _ => (root, files),
+        })
+        .unzip()
+}
+
 /*
  * Test data structure:
  * test_data/state/ +- a/ +- a/ +- a
@@ -181,14 +770,14 @@ fn test_viewer_state_init() {
         "test_data/state/a/b/c.txt",
     ];
 
-    let mut state1 = ViewerState::new("test_data/state/a/b/a.txt", extensions.clone());
+    let mut state1 = ViewerState::new_text(&["test_data/state/a/b/a.txt"], extensions.clone());
     assert_eq!(state1.reload_files(), Ok(()));
     assert_eq!(state1.cursor, 0);
     for (actual, expected) in state1.paths.iter().zip(expected_filenames) {
         assert!(actual.ends_with(expected));
     }
 
-    let mut state2 = ViewerState::new("test_data/state/a/b/b.txt", extensions.clone());
+    let mut state2 = ViewerState::new_text(&["test_data/state/a/b/b.txt"], extensions.clone());
     assert_eq!(state2.reload_files(), Ok(()));
     assert_eq!(state2.cursor, 1);
     for (actual, expected) in state2.paths.iter().zip(expected_filenames) {
@@ -199,7 +788,8 @@ fn test_viewer_state_init() {
 #[test]
 fn test_viewer_state_change_directory() {
     let extensions = HashSet::from([String::from("txt")]);
-    let mut state = ViewerState::new("test_data/state/a/b/a.txt", extensions);
+    let mut state = ViewerState::new_text(&["test_data/state/a/b/a.txt"], extensions);
+    assert_eq!(state.set_root(Path::new("test_data/state")), Ok(()));
     assert_eq!(state.reload_files(), Ok(()));
 
     let prev_expected = [
@@ -226,7 +816,8 @@ fn test_viewer_state_change_directory() {
 #[test]
 fn test_viewer_state_next_cursor() {
     let extensions = HashSet::from([String::from("txt")]);
-    let mut state = ViewerState::new("test_data/state/a/a/b.txt", extensions);
+    let mut state = ViewerState::new_text(&["test_data/state/a/a/b.txt"], extensions);
+    assert_eq!(state.set_root(Path::new("test_data/state")), Ok(()));
     assert_eq!(state.reload_files(), Ok(()));
 
     assert!(state.next_cursor().is_ok());
@@ -253,7 +844,8 @@ fn test_viewer_state_next_cursor() {
 #[test]
 fn test_viewer_state_prev_cursor() {
     let extensions = HashSet::from([String::from("txt")]);
-    let mut state = ViewerState::new("test_data/state/b/a/a.txt", extensions);
+    let mut state = ViewerState::new_text(&["test_data/state/b/a/a.txt"], extensions);
+    assert_eq!(state.set_root(Path::new("test_data/state")), Ok(()));
     assert_eq!(state.reload_files(), Ok(()));
 
     assert!(state.prev_cursor().is_ok());
@@ -283,7 +875,7 @@ fn test_viewer_state_prev_cursor() {
 fn test_viewer_state_move_first() {
     let extensions = HashSet::from([String::from("txt")]);
 
-    let mut state1 = ViewerState::new("test_data/state/a/b/a.txt", extensions.clone());
+    let mut state1 = ViewerState::new_text(&["test_data/state/a/b/a.txt"], extensions.clone());
     assert_eq!(state1.reload_files(), Ok(()));
 
     state1.move_first();
@@ -292,7 +884,7 @@ fn test_viewer_state_move_first() {
         Ok(Path::new("test_data/state/a/b/a.txt").to_path_buf())
     );
 
-    let mut state2 = ViewerState::new("test_data/state/a/b/b.txt", extensions.clone());
+    let mut state2 = ViewerState::new_text(&["test_data/state/a/b/b.txt"], extensions.clone());
     assert_eq!(state2.reload_files(), Ok(()));
 
     state2.move_first();
@@ -301,7 +893,7 @@ fn test_viewer_state_move_first() {
         Ok(Path::new("test_data/state/a/b/a.txt").to_path_buf())
     );
 
-    let mut state3 = ViewerState::new("test_data/state/a/b/c.txt", extensions.clone());
+    let mut state3 = ViewerState::new_text(&["test_data/state/a/b/c.txt"], extensions.clone());
     assert_eq!(state3.reload_files(), Ok(()));
 
     state3.move_first();
@@ -315,7 +907,7 @@ fn test_viewer_state_move_first() {
 fn test_viewer_state_move_last() {
     let extensions = HashSet::from([String::from("txt")]);
 
-    let mut state1 = ViewerState::new("test_data/state/a/a/a.txt", extensions.clone());
+    let mut state1 = ViewerState::new_text(&["test_data/state/a/a/a.txt"], extensions.clone());
     assert_eq!(state1.reload_files(), Ok(()));
 
     state1.move_last();
@@ -324,7 +916,7 @@ fn test_viewer_state_move_last() {
         Ok(Path::new("test_data/state/a/a/c.txt").to_path_buf())
     );
 
-    let mut state2 = ViewerState::new("test_data/state/a/a/b.txt", extensions.clone());
+    let mut state2 = ViewerState::new_text(&["test_data/state/a/a/b.txt"], extensions.clone());
     assert_eq!(state2.reload_files(), Ok(()));
 
     state2.move_last();
@@ -333,7 +925,7 @@ fn test_viewer_state_move_last() {
         Ok(Path::new("test_data/state/a/a/c.txt").to_path_buf())
     );
 
-    let mut state3 = ViewerState::new("test_data/state/a/a/c.txt", extensions.clone());
+    let mut state3 = ViewerState::new_text(&["test_data/state/a/a/c.txt"], extensions.clone());
     assert_eq!(state3.reload_files(), Ok(()));
 
     state3.move_last();
@@ -342,3 +934,372 @@ fn test_viewer_state_move_last() {
         Ok(Path::new("test_data/state/a/a/c.txt").to_path_buf())
     );
 }
+
+#[test]
+fn test_viewer_state_archive() {
+    let extensions = HashSet::from([String::from("png")]);
+
+    let mut state1 = ViewerState::new(&["test_data/archive/b.zip"], extensions.clone());
+    assert_eq!(state1.reload_files(), Ok(()));
+    assert_eq!(
+        state1.get(),
+        Ok(Path::new("test_data/archive/b.zip/a.png").to_path_buf())
+    );
+
+    assert!(state1.next_cursor().is_ok()); // b.zip/b.png
+    assert!(state1.next_cursor().is_ok()); // b.zip/c/a.png
+    assert_eq!(
+        state1.get(),
+        Ok(Path::new("test_data/archive/b.zip/c/a.png").to_path_buf())
+    );
+
+    let mut state2 = ViewerState::new(&["test_data/archive/a/a.png"], extensions);
+    assert_eq!(state2.set_root(Path::new("test_data/archive")), Ok(()));
+    assert_eq!(state2.reload_files(), Ok(()));
+    assert_eq!(state2.next_directory(), Ok(()));
+    assert_eq!(
+        state2.get(),
+        Ok(Path::new("test_data/archive/b.zip/a.png").to_path_buf())
+    );
+}
+
+#[test]
+fn test_viewer_state_sort() {
+    use ipc::{SortKey, SortMode, SortOrder};
+
+    let extensions = HashSet::from([String::from("txt")]);
+    // Natural order is the default: img1, img2, Img3, img10
+    let mut state = ViewerState::new_text(&["test_data/sort/img2.txt"], extensions);
+    assert_eq!(state.reload_files(), Ok(()));
+    assert_eq!(state.cursor, 1);
+    assert!(state.next_cursor().is_ok());
+    assert_eq!(
+        state.get(),
+        Ok(Path::new("test_data/sort/Img3.txt").to_path_buf())
+    );
+    assert!(state.next_cursor().is_ok());
+    assert_eq!(
+        state.get(),
+        Ok(Path::new("test_data/sort/img10.txt").to_path_buf())
+    );
+
+    state.set_sort(SortConfig {
+        files: SortMode {
+            key: SortKey::Size,
+            order: SortOrder::Descending,
+        },
+        ..state.sort()
+    });
+    assert_eq!(state.reload_files(), Ok(()));
+    assert_eq!(state.cursor, 2);
+    assert_eq!(
+        state.get(),
+        Ok(Path::new("test_data/sort/img10.txt").to_path_buf())
+    );
+}
+
+#[test]
+fn test_viewer_state_root() {
+    let extensions = HashSet::from([String::from("txt")]);
+
+    // The directory of the opened file is the default root
+    let mut state1 = ViewerState::new_text(&["test_data/state/a/b/c.txt"], extensions.clone());
+    assert_eq!(state1.root(), Path::new("test_data/state/a/b"));
+    assert_eq!(state1.reload_files(), Ok(()));
+    assert_eq!(
+        state1.next_directory(),
+        Err(ViewerError::EndOfCollection(PathBuf::from(
+            "test_data/state/a/b"
+        )))
+    );
+    assert_eq!(
+        state1.get(),
+        Ok(Path::new("test_data/state/a/b/c.txt").to_path_buf())
+    );
+
+    let mut state2 = ViewerState::new_text(&["test_data/state/a"], extensions.clone());
+    assert_eq!(state2.root(), Path::new("test_data/state/a"));
+    assert_eq!(state2.reload_files(), Ok(()));
+    assert_eq!(
+        state2.prev_cursor().map_err(|err| err.kind()),
+        Err(ipc::ErrorKind::EndOfCollection)
+    );
+    for _ in 0..6 {
+        assert!(state2.next_cursor().is_ok());
+    }
+    assert_eq!(
+        state2.get(),
+        Ok(Path::new("test_data/state/a/b/c.txt").to_path_buf())
+    );
+    assert!(state2.next_cursor().is_err());
+
+    let mut state3 = ViewerState::new_text(&["test_data/state/a/b/c.txt"], extensions);
+    assert!(state3.set_root(Path::new("test_data/state/b")).is_err());
+    assert_eq!(state3.root(), Path::new("test_data/state/a/b"));
+}
+
+#[test]
+fn test_viewer_state_wrap() {
+    let extensions = HashSet::from([String::from("txt")]);
+    let mut state = ViewerState::new_text(&["test_data/state/c/c.txt"], extensions);
+    assert_eq!(state.set_root(Path::new("test_data/state")), Ok(()));
+    assert_eq!(state.reload_files(), Ok(()));
+    assert!(state.next_cursor().is_err());
+
+    state.set_wrap(true);
+    assert!(state.next_cursor().is_ok());
+    assert_eq!(state.wrapped(), Some(Wrap::ToFirst));
+    assert_eq!(
+        state.get(),
+        Ok(Path::new("test_data/state/a/d.txt").to_path_buf())
+    );
+
+    state.clear_wrapped();
+    assert!(state.next_cursor().is_ok()); // a/a/a
+    assert_eq!(state.wrapped(), None);
+    assert!(state.prev_cursor().is_ok()); // a/d
+    assert!(state.prev_cursor().is_ok());
+    assert_eq!(state.wrapped(), Some(Wrap::ToLast));
+    assert_eq!(
+        state.get(),
+        Ok(Path::new("test_data/state/c/c.txt").to_path_buf())
+    );
+}
+
+#[test]
+fn test_viewer_state_roots() {
+    let extensions = HashSet::from([String::from("txt")]);
+
+    // Opened directories start at their first image
+    let mut state1 = ViewerState::new_text(&["test_data/state/b"], extensions.clone());
+    assert_eq!(state1.reload_files(), Ok(()));
+    assert_eq!(
+        state1.get(),
+        Ok(Path::new("test_data/state/b/a/a.txt").to_path_buf())
+    );
+
+    // Multiple paths are traversed in the given order
+    let roots = [
+        "test_data/state/c",
+        "test_data/state/a/a/b.txt",
+        "test_data/state/a/d.txt",
+        "test_data/state/b",
+    ];
+    let mut state2 = ViewerState::new_text(&roots, extensions);
+    assert_eq!(state2.reload_files(), Ok(()));
+    assert_eq!(state2.count(), 3);
+    state2.move_last();
+    assert!(state2.next_cursor().is_ok());
+    assert_eq!(
+        state2.get(),
+        Ok(Path::new("test_data/state/a/a/b.txt").to_path_buf())
+    );
+    assert_eq!(state2.count(), 1);
+    assert!(state2.next_cursor().is_ok()); // a/d
+    assert!(state2.next_cursor().is_ok());
+    assert_eq!(
+        state2.get(),
+        Ok(Path::new("test_data/state/b/a/a.txt").to_path_buf())
+    );
+    assert_eq!(state2.root(), Path::new("test_data/state/b"));
+
+    assert!(state2.prev_directory().is_ok()); // a/d
+    assert!(state2.prev_directory().is_ok());
+    assert_eq!(
+        state2.get(),
+        Ok(Path::new("test_data/state/a/a/b.txt").to_path_buf())
+    );
+    assert!(state2.prev_cursor().is_ok());
+    assert_eq!(
+        state2.get(),
+        Ok(Path::new("test_data/state/c/c.txt").to_path_buf())
+    );
+    assert!(state2.prev_directory().is_err());
+    assert_eq!(state2.root(), Path::new("test_data/state/c"));
+}
+
+#[test]
+fn test_viewer_state_file_group() {
+    let extensions = HashSet::from([String::from("txt")]);
+
+    // Files from the same directory are shown together, without the others
+    let paths = [
+        "test_data/state/a/a/c.txt",
+        "test_data/state/a/a/a.txt",
+        "test_data/state/a/d.txt",
+    ];
+    let mut state = ViewerState::new_text(&paths, extensions);
+    assert_eq!(state.root(), Path::new("test_data/state/a/a"));
+    assert_eq!(state.reload_files(), Ok(()));
+    assert_eq!(state.count(), 2);
+    assert_eq!(
+        state.get(),
+        Ok(Path::new("test_data/state/a/a/c.txt").to_path_buf())
+    );
+    assert!(state.prev_cursor().is_ok());
+    assert_eq!(
+        state.get(),
+        Ok(Path::new("test_data/state/a/a/a.txt").to_path_buf())
+    );
+    assert!(state.prev_directory().is_err());
+
+    state.move_last();
+    assert!(state.next_cursor().is_ok());
+    assert_eq!(state.root(), Path::new("test_data/state/a/d.txt"));
+    assert_eq!(state.count(), 1);
+    assert!(state.prev_cursor().is_ok());
+    assert_eq!(state.root(), Path::new("test_data/state/a/a"));
+    assert_eq!(
+        state.get(),
+        Ok(Path::new("test_data/state/a/a/c.txt").to_path_buf())
+    );
+}
+
+#[test]
+fn test_viewer_state_reopen() {
+    use ipc::{SortKey, SortMode, SortOrder};
+
+    let extensions = HashSet::from([String::from("txt")]);
+    let mut state = ViewerState::new_text::<PathBuf>(&[], extensions);
+    state.set_wrap(true);
+    state.set_sort(SortConfig {
+        files: SortMode {
+            key: SortKey::Name,
+            order: SortOrder::Descending,
+        },
+        ..SortConfig::default()
+    });
+    assert_eq!(state.reload_files(), Err(ViewerError::NothingOpened));
+
+    let (directories, files) = state
+        .list_directory(Path::new("test_data/state/a"))
+        .unwrap();
+    assert_eq!(
+        directories,
+        vec![
+            PathBuf::from("test_data/state/a/a"),
+            PathBuf::from("test_data/state/a/b"),
+            PathBuf::from("test_data/state/a/c"),
+        ]
+    );
+    assert_eq!(files, vec![PathBuf::from("test_data/state/a/d.txt")]);
+
+    let mut state = state.reopen(&files);
+    assert_eq!(state.reload_files(), Ok(()));
+    assert_eq!(state.paths[0], PathBuf::from("test_data/state/a/d.txt"));
+    assert_eq!(state.sort().files.order, SortOrder::Descending);
+    assert!(state.wrap);
+}
+
+#[test]
+fn test_viewer_state_recursive() {
+    use super::index::wait_index;
+
+    let extensions = HashSet::from([String::from("txt")]);
+    let mut state = ViewerState::new_text(&["test_data/state/a/b/a.txt"], extensions);
+    assert_eq!(state.set_root(Path::new("test_data/state")), Ok(()));
+    assert_eq!(state.reload_files(), Ok(()));
+
+    // Without the index, positions are in the current directory
+    assert_eq!(state.seek(100.0), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/a/b/c.txt")));
+    assert_eq!(state.jump_to(1), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/a/b/b.txt")));
+    assert!(state.jump_to(3).is_err());
+
+    assert_eq!(state.jump_to(0), Ok(()));
+    state.set_recursive(true);
+    assert_eq!(state.reload_files(), Ok(()));
+    wait_index(state.index.as_ref().unwrap());
+
+    assert_eq!(state.position(), Some(4));
+    assert_eq!(state.total(), Some(12));
+    assert_eq!(state.prev_directory(), Ok(()));
+    assert_eq!(state.position(), Some(3));
+
+    assert_eq!(state.jump_to(7), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/b/a/a.txt")));
+    assert_eq!(state.next_cursor(), Ok(()));
+    assert_eq!(state.position(), Some(8));
+    assert!(state.jump_to(12).is_err());
+
+    assert_eq!(state.seek(50.0), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/a/b/c.txt")));
+    assert_eq!(state.seek(100.0), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/c/c.txt")));
+    assert_eq!(state.cursor(), 2);
+}
+
+#[test]
+fn test_viewer_state_collection_ends() {
+    let extensions = HashSet::from([String::from("txt")]);
+    let roots = [
+        "test_data/state/a/c",
+        "test_data/state/b",
+        "test_data/state/c/a.txt",
+    ];
+    let mut state = ViewerState::new_text(&roots, extensions);
+    assert_eq!(state.reload_files(), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/b/a/a.txt")));
+
+    assert_eq!(state.move_collection_last(), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/c/a.txt")));
+    assert_eq!(state.root(), Path::new("test_data/state/c/a.txt"));
+
+    // Roots without images are skipped
+    assert_eq!(state.move_collection_first(), Ok(()));
+    assert_eq!(state.get(), Ok(PathBuf::from("test_data/state/b/a/a.txt")));
+    assert_eq!(state.root(), Path::new("test_data/state/b"));
+}
+
+#[test]
+fn test_viewer_state_spread() {
+    let extensions = HashSet::from([String::from("png")]);
+    let mut state = ViewerState::new(&["test_data/spread"], extensions);
+    assert_eq!(state.reload_files(), Ok(()));
+    let page = |name: &str| Path::new("test_data/spread").join(name);
+
+    // Images are shown one by one by default
+    assert_eq!(state.spread_len(), 1);
+    assert_eq!(state.facing(), None);
+
+    // "c" is wide, so it is shown alone
+    state.set_spread(SpreadSettings {
+        enabled: true,
+        ..SpreadSettings::default()
+    });
+    assert_eq!(state.facing(), Some(page("b.png")));
+    assert_eq!(state.next_page(), Ok(()));
+    assert_eq!(state.get(), Ok(page("c.png")));
+    assert_eq!(state.facing(), None);
+    assert_eq!(state.next_page(), Ok(()));
+    assert_eq!(state.get(), Ok(page("d.png")));
+    assert_eq!(state.facing(), Some(page("e.png")));
+    assert_eq!(state.next_page(), Ok(()));
+    assert_eq!(state.get(), Ok(page("f.png")));
+    assert_eq!(state.facing(), None);
+    assert!(state.next_page().is_err());
+    assert_eq!(state.get(), Ok(page("f.png")));
+
+    assert_eq!(state.prev_page(), Ok(()));
+    assert_eq!(state.get(), Ok(page("d.png")));
+    assert_eq!(state.prev_page(), Ok(()));
+    assert_eq!(state.get(), Ok(page("c.png")));
+    assert_eq!(state.prev_page(), Ok(()));
+    assert_eq!(state.get(), Ok(page("a.png")));
+
+    // The cover shifts the pairs before the wide image
+    state.set_spread(SpreadSettings {
+        cover: true,
+        ..state.spread()
+    });
+    assert_eq!(state.facing(), None);
+    assert_eq!(state.next_page(), Ok(()));
+    assert_eq!(state.get(), Ok(page("b.png")));
+    assert_eq!(state.facing(), None);
+
+    // Jumps to the second image of a spread show the whole spread
+    assert_eq!(state.jump_to(4), Ok(()));
+    state.align_spread();
+    assert_eq!(state.get(), Ok(page("d.png")));
+}
@@ -1,52 +1,153 @@
-use super::image::try_get_source_image;
+use std::path::PathBuf;
+
+use mime;
+
+use super::error::{ViewerError, ViewerResult};
+use super::image::detect_image;
 use super::state::ViewerState;
 
-pub fn get_next_image(state: &mut ViewerState, moves: i32) -> Result<String, String> {
-    for _ in 0..moves {
-        if let Err(err) = state.next_cursor() {
-            return Err(err);
+pub struct Versioned<T> {
+    pub value: T,
+    pub generation: u64,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            generation: 0,
         }
     }
+}
+
+// Moves by `moves` pages. With wrap around, the moves left are reduced to less than a round
+// once the page after the first move comes back, so that large counts end.
+fn move_pages<F>(state: &mut ViewerState, moves: usize, step: F) -> ViewerResult<()>
+where
+    F: Fn(&mut ViewerState) -> ViewerResult<()>,
+{
+    let (mut moves, mut first) = (moves, None);
+    let mut i = 0;
+    while i < moves {
+        match step(state) {
+            Ok(_) => (),
+            // Counts beyond the end stop at the last image
+            Err(ViewerError::EndOfCollection(_)) if i > 0 => break,
+            Err(err) => return Err(err),
+        }
+        i += 1;
+        match (&first, state.get().ok()) {
+            (None, current) => first = current,
+            (Some(first), Some(current)) if *first == current => {
+                moves = i + (moves - i) % (i - 1);
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
 
+pub fn get_next_image(
+    state: &mut ViewerState,
+    moves: usize,
+) -> ViewerResult<(PathBuf, mime::Mime)> {
+    move_pages(state, moves, ViewerState::next_page)?;
+    // Jumps may land on the second image of a spread
+    state.align_spread();
+
+    // With wrap around, skipping non-image files would never end without images
+    let start = state.get().ok();
     loop {
         match state.get() {
-            Ok(path) => match try_get_source_image(&path) {
-                Ok(img) => {
+            Ok(path) => match detect_image(&path, state.detector()) {
+                Ok(mimetype) => {
                     log::debug!("Current image: {:?}", path);
-                    return Ok(img);
+                    return Ok((path, mimetype));
                 }
                 Err(err) => log::info!("{:?}", err),
             },
             Err(err) => log::info!("{:?}", err),
         }
 
-        if let Err(err) = state.next_cursor() {
+        if let Err(err) = state.next_page() {
             return Err(err);
         }
+        if state.get().ok() == start {
+            return Err(ViewerError::NoMoreImages);
+        }
     }
 }
 
-pub fn get_prev_image(state: &mut ViewerState, moves: i32) -> Result<String, String> {
-    for _ in moves..0 {
-        if let Err(err) = state.prev_cursor() {
-            return Err(err);
-        }
-    }
+pub fn get_prev_image(
+    state: &mut ViewerState,
+    moves: usize,
+) -> ViewerResult<(PathBuf, mime::Mime)> {
+    move_pages(state, moves, ViewerState::prev_page)?;
+    state.align_spread();
 
+    // With wrap around, skipping non-image files would never end without images
+    let start = state.get().ok();
     loop {
         match state.get() {
-            Ok(path) => match try_get_source_image(&path) {
-                Ok(img) => {
+            Ok(path) => match detect_image(&path, state.detector()) {
+                Ok(mimetype) => {
                     log::debug!("Current image: {:?}", path);
-                    return Ok(img);
+                    return Ok((path, mimetype));
                 }
                 Err(err) => log::info!("{:?}", err),
             },
             Err(err) => log::info!("{:?}", err),
         }
 
-        if let Err(err) = state.prev_cursor() {
+        if let Err(err) = state.prev_page() {
             return Err(err);
         }
+        if state.get().ok() == start {
+            return Err(ViewerError::NoMoreImages);
+        }
     }
 }
+
+#[test]
+fn test_move_image() {
+    use std::collections::HashSet;
+
+    let extensions = HashSet::from([String::from("png")]);
+    let mut state = ViewerState::new(&["test_data/spread/b.png"], extensions);
+    assert_eq!(state.reload_files(), Ok(()));
+    let next = |state: &mut ViewerState, moves| get_next_image(state, moves).map(|(path, _)| path);
+    let prev = |state: &mut ViewerState, moves| get_prev_image(state, moves).map(|(path, _)| path);
+
+    assert_eq!(
+        next(&mut state, 2),
+        Ok(PathBuf::from("test_data/spread/d.png"))
+    );
+    // Counts beyond the end stop at the last image
+    assert_eq!(
+        next(&mut state, 5),
+        Ok(PathBuf::from("test_data/spread/f.png"))
+    );
+    assert!(matches!(
+        next(&mut state, 5),
+        Err(ViewerError::EndOfCollection(_))
+    ));
+    assert_eq!(
+        prev(&mut state, usize::MAX),
+        Ok(PathBuf::from("test_data/spread/a.png"))
+    );
+
+    // Counts over a round of 6 images are reduced with wrap around
+    state.set_wrap(true);
+    assert_eq!(
+        next(&mut state, 7),
+        Ok(PathBuf::from("test_data/spread/b.png"))
+    );
+    assert_eq!(
+        next(&mut state, usize::MAX),
+        Ok(PathBuf::from("test_data/spread/e.png"))
+    );
+    assert_eq!(
+        prev(&mut state, usize::MAX),
+        Ok(PathBuf::from("test_data/spread/b.png"))
+    );
+}
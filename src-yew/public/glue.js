@@ -1,17 +1,9 @@
 const invoke = window.__TAURI__.invoke;
 
-export async function nextImage(moves) {
-  return await invoke("next_image", {moves: moves});
+export async function invokeCommand(name, args) {
+  return await invoke(name, args);
 }
 
-export async function prevImage(moves) {
-  return await invoke("prev_image", {moves: moves});
-}
-
-export async function nextDirectory() {
-  return await invoke("next_directory");
-}
-
-export async function prevDirectory() {
-  return await invoke("prev_directory");
+export async function setTitle(title) {
+  return await window.__TAURI__.window.appWindow.setTitle(title);
 }
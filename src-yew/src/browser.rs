@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use ipc::DirectoryListing;
+use yew::prelude::*;
+
+#[derive(Properties, PartialEq)]
+pub struct DirectoryBrowserProps {
+    pub listing: DirectoryListing,
+    // Called with a directory to list
+    pub on_browse: Callback<PathBuf>,
+    // Called with a file or directory to open as a collection
+    pub on_open: Callback<PathBuf>,
+}
+
+fn get_file_name(path: &PathBuf) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+fn view_entry(
+    path: &PathBuf,
+    class: &'static str,
+    label: String,
+    callback: &Callback<PathBuf>,
+) -> Html {
+    let path = path.clone();
+    let callback = callback.clone();
+    let onclick = Callback::from(move |_| callback.emit(path.clone()));
+    html! {
+        <li class={classes!("entry", class)} onclick={onclick}>
+            { label }
+        </li>
+    }
+}
+
+#[function_component(DirectoryBrowser)]
+pub fn directory_browser(props: &DirectoryBrowserProps) -> Html {
+    let listing = &props.listing;
+    let open_current = {
+        let path = listing.path.clone();
+        let on_open = props.on_open.clone();
+        Callback::from(move |_| on_open.emit(path.clone()))
+    };
+
+    html! {
+        <div class="browser">
+            <div class="browser-header">
+                <span class="browser-path">{ listing.path.to_string_lossy().to_string() }</span>
+                <button onclick={open_current}>{ "Open this directory" }</button>
+            </div>
+            <ul class="browser-entries">
+                {
+                    for listing.parent.iter().map(|parent| {
+                        view_entry(parent, "directory", "..".to_string(), &props.on_browse)
+                    })
+                }
+                {
+                    for listing.directories.iter().map(|dir| {
+                        view_entry(dir, "directory", format!("{}/", get_file_name(dir)), &props.on_browse)
+                    })
+                }
+                {
+                    for listing.files.iter().map(|file| {
+                        view_entry(file, "file", get_file_name(file), &props.on_open)
+                    })
+                }
+            </ul>
+        </div>
+    }
+}
@@ -1,83 +1,223 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use ipc::{
+    Args, ChangeSort, ChangeSpread, Command, DirectoryListing, Error, ErrorKind, GetView,
+    ImageSource, Jump, JumpArgs, JumpTarget, ListArgs, ListDirectory, MoveArgs, NextDirectory,
+    NextImage, OpenArgs, OpenPaths, OrientationArgs, PrevDirectory, PrevImage, RequestArgs,
+    SaveOrientation, SetView, SortArgs, SortChange, SortTarget, SpreadArgs, SpreadChange,
+    Transform, ViewSettings, ARGS_KEY,
+};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 use yew::prelude::Callback;
 
 #[wasm_bindgen(module = "/public/glue.js")]
 extern "C" {
-    #[wasm_bindgen(js_name = nextImage, catch)]
-    async fn next_image(moves: &str) -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(js_name = invokeCommand, catch)]
+    async fn invoke_command(name: &str, args: JsValue) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(js_name = setTitle, catch)]
+    async fn set_title(title: &str) -> Result<JsValue, JsValue>;
+}
+
+pub type SourceHandler = Callback<Result<ImageSource, Error>>;
+pub type ListingHandler = Callback<Result<DirectoryListing, Error>>;
+pub type ViewHandler = Callback<Result<ViewSettings, Error>>;
+pub type SaveHandler = Callback<Result<(), Error>>;
 
-    #[wasm_bindgen(js_name = prevImage, catch)]
-    async fn prev_image(moves: &str) -> Result<JsValue, JsValue>;
+fn internal_error<E: Debug>(err: E) -> Error {
+    Error {
+        kind: ErrorKind::Internal,
+        message: format!("{:?}", err),
+    }
+}
+
+async fn invoke<C: Command>(args: &Args<C>) -> Result<C::Response, Error> {
+    let payload = js_sys::Object::new();
+    let value = match serde_wasm_bindgen::to_value(args) {
+        Ok(value) => value,
+        Err(err) => return Err(internal_error(err)),
+    };
+    if let Err(err) = js_sys::Reflect::set(&payload, &JsValue::from_str(ARGS_KEY), &value) {
+        return Err(internal_error(err));
+    }
+
+    match invoke_command(C::NAME, payload.into()).await {
+        Ok(data) => serde_wasm_bindgen::from_value(data).map_err(internal_error),
+        // Errors which are not raised by the commands (e.g. IPC failures) have no kind
+        Err(err) => match serde_wasm_bindgen::from_value(err.clone()) {
+            Ok(err) => Err(err),
+            Err(_) => Err(internal_error(err)),
+        },
+    }
+}
 
-    #[wasm_bindgen(js_name = nextDirectory, catch)]
-    async fn next_directory() -> Result<JsValue, JsValue>;
+// Only the response of the latest navigation request is applied.
+static LATEST_REQUEST: AtomicU32 = AtomicU32::new(ipc::FIRST_REQUEST_ID - 1);
 
-    #[wasm_bindgen(js_name = prevDirectory, catch)]
-    async fn prev_directory() -> Result<JsValue, JsValue>;
+fn next_request_id() -> u32 {
+    LATEST_REQUEST.fetch_add(1, Ordering::SeqCst) + 1
 }
 
-pub fn fetch_current_image_source(handler: Callback<String>) {
+fn is_latest_request(request_id: u32) -> bool {
+    LATEST_REQUEST.load(Ordering::SeqCst) == request_id
+}
+
+fn handle_response(request_id: u32, response: Result<ImageSource, Error>, handler: SourceHandler) {
+    if !is_latest_request(request_id) {
+        log::debug!("Discard superseded response (request: {:?})", request_id);
+        return;
+    }
+
+    match response {
+        Err(err) if err.kind == ErrorKind::Superseded => log::debug!("{:?}", err),
+        response => handler.emit(response),
+    }
+}
+
+fn fetch_moved_image<C>(moves: usize, handler: SourceHandler)
+where
+    C: Command<Args = MoveArgs, Response = ImageSource> + 'static,
+{
+    let request_id = next_request_id();
     spawn_local(async move {
-        match next_image("0").await {
-            Ok(data) => {
-                if let Some(src) = data.as_string() {
-                    handler.emit(src);
-                }
-            }
-            Err(err) => log::error!("{:?}", err),
-        }
+        let args = MoveArgs { moves, request_id };
+        handle_response(request_id, invoke::<C>(&args).await, handler);
     });
 }
 
-pub fn fetch_next_image_source(handler: Callback<String>) {
+fn fetch_directory<C>(handler: SourceHandler)
+where
+    C: Command<Args = RequestArgs, Response = ImageSource> + 'static,
+{
+    let request_id = next_request_id();
     spawn_local(async move {
-        match next_image("1").await {
-            Ok(data) => {
-                if let Some(src) = data.as_string() {
-                    handler.emit(src);
-                }
-            }
-            Err(err) => log::error!("{:?}", err),
-        }
+        let args = RequestArgs { request_id };
+        handle_response(request_id, invoke::<C>(&args).await, handler);
     });
 }
 
-pub fn fetch_prev_image_source(handler: Callback<String>) {
+pub fn fetch_current_image_source(handler: SourceHandler) {
+    fetch_moved_image::<NextImage>(0, handler);
+}
+
+pub fn fetch_next_image_source(moves: usize, handler: SourceHandler) {
+    fetch_moved_image::<NextImage>(moves, handler);
+}
+
+pub fn fetch_prev_image_source(moves: usize, handler: SourceHandler) {
+    fetch_moved_image::<PrevImage>(moves, handler);
+}
+
+pub fn fetch_next_directory(handler: SourceHandler) {
+    fetch_directory::<NextDirectory>(handler);
+}
+
+pub fn fetch_prev_directory(handler: SourceHandler) {
+    fetch_directory::<PrevDirectory>(handler);
+}
+
+fn change_sort(target: SortTarget, change: SortChange, handler: SourceHandler) {
+    let request_id = next_request_id();
     spawn_local(async move {
-        match prev_image("1").await {
-            Ok(data) => {
-                if let Some(src) = data.as_string() {
-                    handler.emit(src);
-                }
-            }
-            Err(err) => log::error!("{:?}", err),
-        }
+        let args = SortArgs {
+            target,
+            change,
+            request_id,
+        };
+        handle_response(request_id, invoke::<ChangeSort>(&args).await, handler);
     });
 }
 
-pub fn fetch_next_directory(handler: Callback<String>) {
+pub fn next_file_sort(handler: SourceHandler) {
+    change_sort(SortTarget::Files, SortChange::NextKey, handler);
+}
+
+pub fn reverse_file_sort(handler: SourceHandler) {
+    change_sort(SortTarget::Files, SortChange::Reverse, handler);
+}
+
+pub fn next_directory_sort(handler: SourceHandler) {
+    change_sort(SortTarget::Directories, SortChange::NextKey, handler);
+}
+
+pub fn reverse_directory_sort(handler: SourceHandler) {
+    change_sort(SortTarget::Directories, SortChange::Reverse, handler);
+}
+
+fn change_spread(change: SpreadChange, handler: SourceHandler) {
+    let request_id = next_request_id();
+    spawn_local(async move {
+        let args = SpreadArgs { change, request_id };
+        handle_response(request_id, invoke::<ChangeSpread>(&args).await, handler);
+    });
+}
+
+pub fn toggle_spread(handler: SourceHandler) {
+    change_spread(SpreadChange::Toggle, handler);
+}
+
+pub fn reverse_reading_direction(handler: SourceHandler) {
+    change_spread(SpreadChange::ReverseDirection, handler);
+}
+
+pub fn toggle_cover(handler: SourceHandler) {
+    change_spread(SpreadChange::ToggleCover, handler);
+}
+
+pub fn jump(target: JumpTarget, handler: SourceHandler) {
+    let request_id = next_request_id();
+    spawn_local(async move {
+        let args = JumpArgs { target, request_id };
+        handle_response(request_id, invoke::<Jump>(&args).await, handler);
+    });
+}
+
+pub fn open_paths(paths: Vec<PathBuf>, handler: SourceHandler) {
+    let request_id = next_request_id();
     spawn_local(async move {
-        match next_directory().await {
-            Ok(data) => {
-                if let Some(src) = data.as_string() {
-                    handler.emit(src);
-                }
-            }
-            Err(err) => log::error!("{:?}", err),
+        let args = OpenArgs { paths, request_id };
+        handle_response(request_id, invoke::<OpenPaths>(&args).await, handler);
+    });
+}
+
+// Listing does not move the cursor, so every response is applied
+pub fn list_directory(path: Option<PathBuf>, handler: ListingHandler) {
+    spawn_local(async move {
+        let args = ListArgs { path };
+        handler.emit(invoke::<ListDirectory>(&args).await);
+    });
+}
+
+pub fn get_view(handler: ViewHandler) {
+    spawn_local(async move {
+        handler.emit(invoke::<GetView>(&()).await);
+    });
+}
+
+// The view is saved with the reading position, so failures are only logged
+pub fn set_view(settings: ViewSettings) {
+    spawn_local(async move {
+        if let Err(err) = invoke::<SetView>(&settings).await {
+            log::info!("{:?}", err);
         }
     });
 }
 
-pub fn fetch_prev_directory(handler: Callback<String>) {
+pub fn save_orientation(path: PathBuf, transform: Transform, handler: SaveHandler) {
+    spawn_local(async move {
+        let args = OrientationArgs { path, transform };
+        handler.emit(invoke::<SaveOrientation>(&args).await);
+    });
+}
+
+pub fn update_window_title(title: String) {
     spawn_local(async move {
-        match prev_directory().await {
-            Ok(data) => {
-                if let Some(src) = data.as_string() {
-                    handler.emit(src);
-                }
-            }
-            Err(err) => log::error!("{:?}", err),
+        if let Err(err) = set_title(&title).await {
+            log::info!("{:?}", err);
         }
     });
 }
@@ -1,16 +1,69 @@
-use std::boxed::Box;
 use std::collections::HashMap;
 
-use yew::prelude::*;
+use ipc::{FitMode, JumpTarget, ReadingDirection};
 use web_sys::window;
 
-use super::command;
+use super::command::{self, SourceHandler};
+use super::viewport::ViewAction;
+
+// Actions receive the count typed before the key, if any (e.g. 5 of "5l")
+pub type Action = dyn Fn(Option<usize>, SourceHandler) -> ();
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy)]
+pub enum KeyBinding {
+    // Sends a command to the backend
+    Command(&'static Action),
+    // Turns to the page on a side, which depends on the reading direction
+    Page(Side),
+    // Changes the view of the current image
+    View(ViewAction),
+}
 
 enum KeyAction {
     NextImage,
     PrevImage,
+    LeftPage,
+    RightPage,
     NextDirectory,
     PrevDirectory,
+    FirstImage,
+    LastImage,
+    FirstInCollection,
+    LastInCollection,
+    JumpTo,
+    Seek,
+    NextFileSort,
+    ReverseFileSort,
+    NextDirectorySort,
+    ReverseDirectorySort,
+    ToggleSpread,
+    ReverseReadingDirection,
+    ToggleCover,
+    ZoomIn,
+    ZoomOut,
+    ResetZoom,
+    FitWindow,
+    FitWidth,
+    FitHeight,
+    ActualSize,
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    TogglePixelArt,
+    TogglePixelGrid,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    FlipHorizontal,
+    FlipVertical,
+    SaveOrientation,
     Quit,
 }
 
@@ -19,39 +72,179 @@ impl KeyAction {
         match self {
             KeyAction::NextImage => "NEXT_IMAGE",
             KeyAction::PrevImage => "PREV_IMAGE",
+            KeyAction::LeftPage => "LEFT_PAGE",
+            KeyAction::RightPage => "RIGHT_PAGE",
             KeyAction::NextDirectory => "NEXT_DIRECTORY",
             KeyAction::PrevDirectory => "PREV_DIRECTORY",
+            KeyAction::FirstImage => "FIRST_IMAGE",
+            KeyAction::LastImage => "LAST_IMAGE",
+            KeyAction::FirstInCollection => "FIRST_IN_COLLECTION",
+            KeyAction::LastInCollection => "LAST_IN_COLLECTION",
+            KeyAction::JumpTo => "JUMP_TO",
+            KeyAction::Seek => "SEEK",
+            KeyAction::NextFileSort => "NEXT_FILE_SORT",
+            KeyAction::ReverseFileSort => "REVERSE_FILE_SORT",
+            KeyAction::NextDirectorySort => "NEXT_DIRECTORY_SORT",
+            KeyAction::ReverseDirectorySort => "REVERSE_DIRECTORY_SORT",
+            KeyAction::ToggleSpread => "TOGGLE_SPREAD",
+            KeyAction::ReverseReadingDirection => "REVERSE_READING_DIRECTION",
+            KeyAction::ToggleCover => "TOGGLE_COVER",
+            KeyAction::ZoomIn => "ZOOM_IN",
+            KeyAction::ZoomOut => "ZOOM_OUT",
+            KeyAction::ResetZoom => "RESET_ZOOM",
+            KeyAction::FitWindow => "FIT_WINDOW",
+            KeyAction::FitWidth => "FIT_WIDTH",
+            KeyAction::FitHeight => "FIT_HEIGHT",
+            KeyAction::ActualSize => "ACTUAL_SIZE",
+            KeyAction::PanLeft => "PAN_LEFT",
+            KeyAction::PanRight => "PAN_RIGHT",
+            KeyAction::PanUp => "PAN_UP",
+            KeyAction::PanDown => "PAN_DOWN",
+            KeyAction::TogglePixelArt => "TOGGLE_PIXEL_ART",
+            KeyAction::TogglePixelGrid => "TOGGLE_PIXEL_GRID",
+            KeyAction::RotateCw => "ROTATE_CW",
+            KeyAction::RotateCcw => "ROTATE_CCW",
+            KeyAction::Rotate180 => "ROTATE_180",
+            KeyAction::FlipHorizontal => "FLIP_HORIZONTAL",
+            KeyAction::FlipVertical => "FLIP_VERTICAL",
+            KeyAction::SaveOrientation => "SAVE_ORIENTATION",
             KeyAction::Quit => "QUIT",
         }
         .to_string()
     }
 }
 
-pub fn close_window(_: Callback<String>) {
+pub fn close_window(_: Option<usize>, _: SourceHandler) {
     if let Err(err) = window().unwrap().close() {
         log::info!("{:?}", err);
     }
 }
 
-const KEY_ACTION_MAP: [(KeyAction, &dyn Fn(Callback<String>) -> ()); 5] = [
-    (KeyAction::NextImage, &command::fetch_next_image_source),
-    (KeyAction::PrevImage, &command::fetch_prev_image_source),
-    (KeyAction::NextDirectory, &command::fetch_next_directory),
-    (KeyAction::PrevDirectory, &command::fetch_prev_directory),
+fn next_image(count: Option<usize>, handler: SourceHandler) {
+    command::fetch_next_image_source(count.unwrap_or(1), handler);
+}
+
+fn prev_image(count: Option<usize>, handler: SourceHandler) {
+    command::fetch_prev_image_source(count.unwrap_or(1), handler);
+}
+
+// The next page is on the right of left-to-right books, and on the left of right-to-left ones
+pub fn turn_page(
+    side: Side,
+    direction: ReadingDirection,
+    count: Option<usize>,
+    handler: SourceHandler,
+) {
+    match (side, direction) {
+        (Side::Left, ReadingDirection::LeftToRight)
+        | (Side::Right, ReadingDirection::RightToLeft) => prev_image(count, handler),
+        _ => next_image(count, handler),
+    }
+}
+
+// "5G" jumps to the fifth image, and "G" to the last one as in vim
+fn jump_to(count: Option<usize>, handler: SourceHandler) {
+    match count {
+        Some(count) => command::jump(JumpTarget::Position(count.saturating_sub(1)), handler),
+        None => command::jump(JumpTarget::LastInCollection, handler),
+    }
+}
+
+// "50%" seeks to the middle of the collection
+fn seek(count: Option<usize>, handler: SourceHandler) {
+    match count {
+        Some(percent) => command::jump(JumpTarget::Percent(percent as f64), handler),
+        None => log::debug!("Seek needs a count"),
+    }
+}
+
+const KEY_ACTION_MAP: [(KeyAction, &Action); 18] = [
+    (KeyAction::NextImage, &next_image),
+    (KeyAction::PrevImage, &prev_image),
+    (KeyAction::NextDirectory, &|_, handler| {
+        command::fetch_next_directory(handler)
+    }),
+    (KeyAction::PrevDirectory, &|_, handler| {
+        command::fetch_prev_directory(handler)
+    }),
+    (KeyAction::FirstImage, &|_, handler| {
+        command::jump(JumpTarget::First, handler)
+    }),
+    (KeyAction::LastImage, &|_, handler| {
+        command::jump(JumpTarget::Last, handler)
+    }),
+    (KeyAction::FirstInCollection, &|_, handler| {
+        command::jump(JumpTarget::FirstInCollection, handler)
+    }),
+    (KeyAction::LastInCollection, &|_, handler| {
+        command::jump(JumpTarget::LastInCollection, handler)
+    }),
+    (KeyAction::JumpTo, &jump_to),
+    (KeyAction::Seek, &seek),
+    (KeyAction::NextFileSort, &|_, handler| {
+        command::next_file_sort(handler)
+    }),
+    (KeyAction::ReverseFileSort, &|_, handler| {
+        command::reverse_file_sort(handler)
+    }),
+    (KeyAction::NextDirectorySort, &|_, handler| {
+        command::next_directory_sort(handler)
+    }),
+    (KeyAction::ReverseDirectorySort, &|_, handler| {
+        command::reverse_directory_sort(handler)
+    }),
+    (KeyAction::ToggleSpread, &|_, handler| {
+        command::toggle_spread(handler)
+    }),
+    (KeyAction::ReverseReadingDirection, &|_, handler| {
+        command::reverse_reading_direction(handler)
+    }),
+    (KeyAction::ToggleCover, &|_, handler| {
+        command::toggle_cover(handler)
+    }),
     (KeyAction::Quit, &close_window),
 ];
 
-pub fn create_keymap(
-    keyset: HashMap<String, String>,
-) -> HashMap<String, Box<&'static dyn Fn(Callback<String>) -> ()>> {
-    let actions: HashMap<String, Box<&dyn Fn(Callback<String>) -> ()>> =
-        HashMap::from_iter(KEY_ACTION_MAP.map(|(k, func)| (k.as_string(), Box::new(func))));
+const PAGE_ACTION_MAP: [(KeyAction, Side); 2] = [
+    (KeyAction::LeftPage, Side::Left),
+    (KeyAction::RightPage, Side::Right),
+];
+
+const VIEW_ACTION_MAP: [(KeyAction, ViewAction); 19] = [
+    (KeyAction::ZoomIn, ViewAction::ZoomIn),
+    (KeyAction::ZoomOut, ViewAction::ZoomOut),
+    (KeyAction::ResetZoom, ViewAction::ResetZoom),
+    (KeyAction::FitWindow, ViewAction::Fit(FitMode::Contain)),
+    (KeyAction::FitWidth, ViewAction::Fit(FitMode::Width)),
+    (KeyAction::FitHeight, ViewAction::Fit(FitMode::Height)),
+    (KeyAction::ActualSize, ViewAction::Fit(FitMode::Original)),
+    (KeyAction::PanLeft, ViewAction::PanLeft),
+    (KeyAction::PanRight, ViewAction::PanRight),
+    (KeyAction::PanUp, ViewAction::PanUp),
+    (KeyAction::PanDown, ViewAction::PanDown),
+    (KeyAction::TogglePixelArt, ViewAction::TogglePixelArt),
+    (KeyAction::TogglePixelGrid, ViewAction::TogglePixelGrid),
+    (KeyAction::RotateCw, ViewAction::RotateCw),
+    (KeyAction::RotateCcw, ViewAction::RotateCcw),
+    (KeyAction::Rotate180, ViewAction::Rotate180),
+    (KeyAction::FlipHorizontal, ViewAction::FlipHorizontal),
+    (KeyAction::FlipVertical, ViewAction::FlipVertical),
+    (KeyAction::SaveOrientation, ViewAction::SaveOrientation),
+];
+
+pub fn create_keymap(keyset: HashMap<String, String>) -> HashMap<String, KeyBinding> {
+    let actions: HashMap<String, KeyBinding> = KEY_ACTION_MAP
+        .map(|(k, func)| (k.as_string(), KeyBinding::Command(func)))
+        .into_iter()
+        .chain(PAGE_ACTION_MAP.map(|(k, side)| (k.as_string(), KeyBinding::Page(side))))
+        .chain(VIEW_ACTION_MAP.map(|(k, action)| (k.as_string(), KeyBinding::View(action))))
+        .collect();
 
     keyset.iter().fold(
-        HashMap::<String, Box<&dyn Fn(Callback<String>) -> ()>>::new(),
+        HashMap::<String, KeyBinding>::new(),
         |mut map, (key, action)| {
             if let Some(func) = actions.get(action) {
-                map.insert(key.clone(), func.clone());
+                map.insert(key.clone(), *func);
             }
             map
         },
@@ -1,37 +1,380 @@
-use std::boxed::Box;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use gloo::events::EventListener;
+use gloo::timers::callback::Timeout;
+use ipc::{
+    DirectoryListing, Error, ErrorKind, ImageSource, ReadingDirection, SortConfig, SortMode,
+    SortOrder, SpreadSettings, Transform, ViewSettings, Wrap,
+};
 use wasm_bindgen::{JsCast, UnwrapThrowExt};
 use wasm_logger;
-use web_sys::KeyboardEvent;
+use web_sys::{HtmlImageElement, KeyboardEvent, MouseEvent, WheelEvent};
 use yew::prelude::*;
 
+mod browser;
 mod command;
 mod key_action;
+mod viewport;
 
-const DEFAULT_KEYMAP: [(&str, &str); 9] = [
-    ("ArrowRight", "NEXT_IMAGE"),
-    ("l", "NEXT_IMAGE"),
-    ("ArrowLeft", "PREV_IMAGE"),
-    ("h", "PREV_IMAGE"),
+const WINDOW_TITLE: &str = "SimpleImageViewer-rs";
+const NOTIFICATION_TIMEOUT_MS: u32 = 3000;
+
+const DEFAULT_KEYMAP: [(&str, &str); 45] = [
+    ("ArrowRight", "RIGHT_PAGE"),
+    ("l", "RIGHT_PAGE"),
+    ("ArrowLeft", "LEFT_PAGE"),
+    ("h", "LEFT_PAGE"),
+    (" ", "NEXT_IMAGE"),
+    ("Backspace", "PREV_IMAGE"),
     ("ArrowUp", "PREV_DIRECTORY"),
     ("k", "PREV_DIRECTORY"),
     ("ArrowDown", "NEXT_DIRECTORY"),
     ("j", "NEXT_DIRECTORY"),
+    ("^", "FIRST_IMAGE"),
+    ("$", "LAST_IMAGE"),
+    ("Home", "FIRST_IN_COLLECTION"),
+    ("End", "LAST_IN_COLLECTION"),
+    ("g", "FIRST_IN_COLLECTION"),
+    ("G", "JUMP_TO"),
+    ("%", "SEEK"),
+    ("s", "NEXT_FILE_SORT"),
+    ("o", "REVERSE_FILE_SORT"),
+    ("S", "NEXT_DIRECTORY_SORT"),
+    ("O", "REVERSE_DIRECTORY_SORT"),
+    ("d", "TOGGLE_SPREAD"),
+    ("b", "REVERSE_READING_DIRECTION"),
+    ("c", "TOGGLE_COVER"),
+    ("+", "ZOOM_IN"),
+    ("=", "ZOOM_IN"),
+    ("-", "ZOOM_OUT"),
+    ("0", "RESET_ZOOM"),
+    ("f", "FIT_WINDOW"),
+    ("w", "FIT_WIDTH"),
+    ("v", "FIT_HEIGHT"),
+    ("a", "ACTUAL_SIZE"),
+    ("H", "PAN_LEFT"),
+    ("L", "PAN_RIGHT"),
+    ("K", "PAN_UP"),
+    ("J", "PAN_DOWN"),
+    ("p", "TOGGLE_PIXEL_ART"),
+    ("#", "TOGGLE_PIXEL_GRID"),
+    ("r", "ROTATE_CW"),
+    ("R", "ROTATE_CCW"),
+    ("t", "ROTATE_180"),
+    ("m", "FLIP_HORIZONTAL"),
+    ("M", "FLIP_VERTICAL"),
+    ("W", "SAVE_ORIENTATION"),
     ("q", "QUIT"),
 ];
 
+// Pressed on the way to "G" or "%", so they keep the count
+const MODIFIER_KEYS: [&str; 4] = ["Shift", "Control", "Alt", "Meta"];
+// Each move is taken one by one, which would not end with wrapping around
+const MAX_COUNT: usize = 99999;
+
 enum ImageViewMsg {
     OnKeyPress(KeyboardEvent),
-    OnSourceChange(String),
+    OnSourceChange(ImageSource),
+    OnError(Error),
+    OnNotificationExpire,
+    OnListing(DirectoryListing),
+    Browse(PathBuf),
+    Open(PathBuf),
+    OnImageLoad(Event),
+    OnViewSettings(ViewSettings),
+    // Zooms around the mouse cursor
+    OnWheel(WheelEvent),
+    OnMouseMove(MouseEvent),
+    OnMouseLeave,
+    OnResize,
+    OnOrientationSaved(Result<(), Error>),
+}
+
+struct Notification {
+    level: &'static str,
+    message: String,
+}
+
+impl From<Error> for Notification {
+    fn from(err: Error) -> Self {
+        // Reaching the end of the collection is an expected result rather than a failure
+        let level = match err.kind {
+            ErrorKind::NoMoreImages
+            | ErrorKind::RootReached
+            | ErrorKind::EndOfCollection
+            | ErrorKind::Indexing => "info",
+            _ => "error",
+        };
+        Self {
+            level,
+            message: err.message,
+        }
+    }
+}
+
+fn format_sort_mode(mode: SortMode) -> String {
+    let order = match mode.order {
+        SortOrder::Ascending => "asc",
+        SortOrder::Descending => "desc",
+    };
+    format!("{}:{}", mode.key.name(), order)
+}
+
+fn format_sort(sort: SortConfig) -> String {
+    format!(
+        "Sort: files {}, directories {}",
+        format_sort_mode(sort.files),
+        format_sort_mode(sort.directories)
+    )
+}
+
+fn format_spread(spread: SpreadSettings) -> String {
+    let direction = match spread.direction {
+        ReadingDirection::LeftToRight => "left to right",
+        ReadingDirection::RightToLeft => "right to left",
+    };
+    match (spread.enabled, spread.cover) {
+        (false, _) => format!("Single page, {}", direction),
+        (true, false) => format!("Spread, {}", direction),
+        (true, true) => format!("Spread with a cover, {}", direction),
+    }
+}
+
+// Names of both pages of a spread, in the reading order
+fn format_file_name(src: &ImageSource) -> String {
+    match &src.facing {
+        Some(facing) => format!("{}, {}", src.file_name, facing.file_name),
+        None => src.file_name.clone(),
+    }
+}
+
+// Displayed size of an image, if known
+fn image_size(src: &ImageSource) -> Option<viewport::Size> {
+    match (src.width, src.height) {
+        (Some(width), Some(height)) => Some((width as f64, height as f64)),
+        _ => None,
+    }
+}
+
+// Natural size of an image as it is stored, before its orientation is applied
+fn stored_size(src: &ImageSource) -> Option<viewport::Size> {
+    image_size(src).map(|(width, height)| match src.orientation.is_transposed() {
+        true => (height, width),
+        false => (width, height),
+    })
+}
+
+// Natural size of a spread, whose pages are scaled to the height of the taller one
+fn spread_size(src: &ImageSource) -> Option<viewport::Size> {
+    let facing = src.facing.as_ref()?;
+    match (src.width, src.height, facing.width, facing.height) {
+        (Some(width1), Some(height1), Some(width2), Some(height2)) => {
+            let height = height1.max(height2) as f64;
+            let width1 = width1 as f64 * height / height1 as f64;
+            let width2 = width2 as f64 * height / height2 as f64;
+            Some((width1 + width2, height))
+        }
+        _ => None,
+    }
+}
+
+// "51900" -> "51,900"
+fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    digits
+        .chars()
+        .enumerate()
+        .fold(String::new(), |mut formatted, (i, digit)| {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                formatted.push(',');
+            }
+            formatted.push(digit);
+            formatted
+        })
+}
+
+// The position in the whole collection is preferred, and the total is unknown until indexed
+fn format_position(src: &ImageSource) -> String {
+    match (src.position, src.total) {
+        (Some(position), Some(total)) => {
+            format!("{} / {}", format_count(position + 1), format_count(total))
+        }
+        (Some(position), None) => format!("{} / ...", format_count(position + 1)),
+        _ => format!("{}/{}", src.index + 1, src.count),
+    }
+}
+
+fn format_wrap(wrap: Wrap) -> (&'static str, String) {
+    match wrap {
+        Wrap::ToFirst => ("first", "Wrapped around to the first image".to_string()),
+        Wrap::ToLast => ("last", "Wrapped around to the last image".to_string()),
+    }
+}
+
+fn create_source_handler(ctx: &Context<ImageViewModel>) -> command::SourceHandler {
+    ctx.link()
+        .callback(|response: Result<ImageSource, Error>| match response {
+            Ok(src) => ImageViewMsg::OnSourceChange(src),
+            Err(err) => ImageViewMsg::OnError(err),
+        })
+}
+
+fn create_listing_handler(ctx: &Context<ImageViewModel>) -> command::ListingHandler {
+    ctx.link()
+        .callback(|response: Result<DirectoryListing, Error>| match response {
+            Ok(listing) => ImageViewMsg::OnListing(listing),
+            Err(err) => ImageViewMsg::OnError(err),
+        })
+}
+
+fn create_view_handler(ctx: &Context<ImageViewModel>) -> command::ViewHandler {
+    ctx.link()
+        .callback(|response: Result<ViewSettings, Error>| match response {
+            Ok(settings) => ImageViewMsg::OnViewSettings(settings),
+            Err(err) => ImageViewMsg::OnError(err),
+        })
 }
 
 struct ImageViewModel {
-    source: RefCell<String>,
-    keymap: HashMap<String, Box<&'static dyn Fn(Callback<String>) -> ()>>,
+    source: RefCell<Option<ImageSource>>,
+    // Directory browser shown until a collection is opened
+    listing: Option<DirectoryListing>,
+    notification: Option<Notification>,
+    notification_timer: Option<Timeout>,
+    // Edge of the collection which the last move has wrapped around to
+    boundary: Option<&'static str>,
+    // Zoom and pan are kept while moving between images
+    viewport: viewport::Viewport,
+    // Natural size of the current image, once it has been loaded
+    image_size: Option<viewport::Size>,
+    // Opening a collection restores the view saved with it
+    view_outdated: bool,
+    // Image pixel under the mouse cursor, shown in the pixel-art mode
+    hovered_pixel: Option<(u32, u32)>,
+    keymap: HashMap<String, key_action::KeyBinding>,
+    // Count typed before a key action
+    count: Option<usize>,
     keybord_listener: Option<EventListener>,
+    resize_listener: Option<EventListener>,
+}
+
+impl ImageViewModel {
+    fn notify(&mut self, ctx: &Context<Self>, notification: Notification) {
+        let expire = ctx.link().callback(|_| ImageViewMsg::OnNotificationExpire);
+        self.notification_timer = Some(Timeout::new(NOTIFICATION_TIMEOUT_MS, move || {
+            expire.emit(())
+        }));
+        self.notification = Some(notification);
+    }
+
+    fn is_spread(&self) -> bool {
+        matches!(self.source.borrow().as_ref(), Some(src) if src.facing.is_some())
+    }
+
+    fn save_orientation(&mut self, ctx: &Context<Self>) {
+        let transform = self.viewport.transform;
+        let target = self
+            .source
+            .borrow()
+            .as_ref()
+            .map(|src| (src.path.clone(), src.facing.is_some()));
+        match target {
+            // Each page of a spread has its own orientation
+            Some((_, true)) => self.notify(
+                ctx,
+                Notification {
+                    level: "info",
+                    message: "Orientations of spreads cannot be saved".to_string(),
+                },
+            ),
+            Some((path, false)) if transform != Transform::default() => command::save_orientation(
+                path,
+                transform,
+                ctx.link().callback(ImageViewMsg::OnOrientationSaved),
+            ),
+            _ => log::debug!("No orientation to save"),
+        }
+    }
+
+    // Pages of a spread are laid out in the reading direction
+    fn view_image(&self, ctx: &Context<Self>) -> Html {
+        let window = viewport::window_size();
+        let pixelated = self.viewport.pixel_art.then(|| "pixelated");
+        let source = self.source.borrow();
+        let spread = source
+            .as_ref()
+            .and_then(|src| src.facing.as_ref().map(|facing| (src, facing)));
+        match spread {
+            Some((src, facing)) => {
+                let direction = match src.spread.direction {
+                    ReadingDirection::LeftToRight => None,
+                    ReadingDirection::RightToLeft => Some("right-to-left"),
+                };
+                // Pages are scaled to the same height, so they share the width by their ratios
+                let pages = [src, facing.as_ref()]
+                    .map(|page| (page, image_size(page).unwrap_or((1.0, 1.0))));
+                let total: f64 = pages
+                    .iter()
+                    .map(|(_, (width, height))| width / height)
+                    .sum();
+                html! {
+                    <div
+                        class={classes!("spread", direction)}
+                        style={self.viewport.style(self.image_size, window)}
+                    >
+                        {
+                            for pages.iter().map(|(page, size)| {
+                                let share = size.0 / size.1 / total;
+                                let (page_style, image_style) =
+                                    viewport::page_style(*size, share, page.orientation);
+                                html! {
+                                    <div class="page" style={page_style}>
+                                        <img
+                                            class={classes!(pixelated)}
+                                            src={page.url.clone()}
+                                            style={image_style}
+                                            draggable="false"
+                                        />
+                                    </div>
+                                }
+                            })
+                        }
+                    </div>
+                }
+            }
+            None => html! {
+                <>
+                    <img
+                        class={classes!(pixelated)}
+                        src={source.as_ref().map(|src| src.url.clone()).unwrap_or_default()}
+                        style={self.viewport.style(self.image_size, window)}
+                        draggable="false"
+                        onload={ctx.link().callback(ImageViewMsg::OnImageLoad)}
+                    />
+                    {
+                        for self
+                            .viewport
+                            .grid_style(self.image_size, window)
+                            .map(|style| html! { <div class="pixel-grid" style={style} /> })
+                    }
+                </>
+            },
+        }
+    }
+
+    fn update_view<F>(&mut self, modifier: F)
+    where
+        F: FnOnce(&mut viewport::Viewport, viewport::Size) -> bool,
+    {
+        let settings = self.viewport.settings;
+        if modifier(&mut self.viewport, viewport::window_size())
+            && self.viewport.settings != settings
+        {
+            command::set_view(self.viewport.settings);
+        }
+    }
 }
 
 impl Component for ImageViewModel {
@@ -40,42 +383,284 @@ impl Component for ImageViewModel {
 
     fn create(_ctx: &Context<Self>) -> Self {
         Self {
-            source: RefCell::new("".to_string()),
+            source: RefCell::new(None),
+            listing: None,
+            notification: None,
+            notification_timer: None,
+            boundary: None,
+            viewport: viewport::Viewport::default(),
+            image_size: None,
+            view_outdated: false,
+            hovered_pixel: None,
             keymap: key_action::create_keymap(
                 DEFAULT_KEYMAP
                     .iter()
                     .map(|(action, key)| (action.to_string(), key.to_string()))
                     .collect(),
             ),
+            count: None,
             keybord_listener: None,
+            resize_listener: None,
         }
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
+            Self::Message::OnKeyPress(e) if MODIFIER_KEYS.contains(&e.key().as_str()) => (),
             Self::Message::OnKeyPress(e) => {
-                let set_source = ctx
-                    .link()
-                    .callback(|src: String| Self::Message::OnSourceChange(src));
-                match self.keymap.get(&e.key()) {
-                    Some(action) => action(set_source),
+                // A leading "0" is not a count, as in vim
+                let digit = match e.key().parse::<usize>() {
+                    Ok(0) if self.count.is_none() => None,
+                    Ok(digit) if digit < 10 => Some(digit),
+                    _ => None,
+                };
+                if let Some(digit) = digit {
+                    let count = self.count.unwrap_or(0) * 10 + digit;
+                    self.count = Some(count.min(MAX_COUNT));
+                    return false;
+                }
+
+                let count = self.count.take();
+                let set_source = create_source_handler(ctx);
+                match self.keymap.get(&e.key()).copied() {
+                    Some(key_action::KeyBinding::Command(action)) => action(count, set_source),
+                    Some(key_action::KeyBinding::Page(side)) => {
+                        let direction = self
+                            .source
+                            .borrow()
+                            .as_ref()
+                            .map(|src| src.spread.direction)
+                            .unwrap_or_default();
+                        key_action::turn_page(side, direction, count, set_source)
+                    }
+                    Some(key_action::KeyBinding::View(viewport::ViewAction::SaveOrientation)) => {
+                        self.save_orientation(ctx);
+                        return true;
+                    }
+                    Some(key_action::KeyBinding::View(action)) => {
+                        let image = self.image_size;
+                        self.update_view(|viewport, window| viewport.apply(action, image, window));
+                        return true;
+                    }
                     _ => (),
                 }
             }
             Self::Message::OnSourceChange(src) => {
-                self.source.replace(src);
+                command::update_window_title(format!(
+                    "{} ({}) - {}",
+                    format_file_name(&src),
+                    format_position(&src),
+                    WINDOW_TITLE
+                ));
+                let prev = self
+                    .source
+                    .borrow()
+                    .as_ref()
+                    .map(|prev| (prev.sort, prev.spread));
+                self.boundary = None;
+                match (src.wrapped, prev) {
+                    (Some(wrap), _) => {
+                        let (boundary, message) = format_wrap(wrap);
+                        self.notify(
+                            ctx,
+                            Notification {
+                                level: "info",
+                                message,
+                            },
+                        );
+                        self.boundary = Some(boundary);
+                    }
+                    (None, Some((sort, _))) if sort != src.sort => self.notify(
+                        ctx,
+                        Notification {
+                            level: "info",
+                            message: format_sort(src.sort),
+                        },
+                    ),
+                    (None, Some((_, spread))) if spread != src.spread => self.notify(
+                        ctx,
+                        Notification {
+                            level: "info",
+                            message: format_spread(src.spread),
+                        },
+                    ),
+                    _ => {
+                        self.notification = None;
+                        self.notification_timer = None;
+                    }
+                }
+                // The size is read from the image once it is loaded, except for spreads
+                if self.source.borrow().as_ref().map(|prev| &prev.url) != Some(&src.url) {
+                    self.image_size = spread_size(&src);
+                    // Pages of a spread are turned upright one by one
+                    self.viewport.orientation = match src.facing {
+                        Some(_) => Transform::default(),
+                        None => src.orientation,
+                    };
+                    self.viewport.transform = Transform::default();
+                    if let Some(size) = self.image_size {
+                        self.viewport.reset_offset(size, viewport::window_size());
+                    }
+                }
+                if self.view_outdated {
+                    command::get_view(create_view_handler(ctx));
+                    self.view_outdated = false;
+                }
+                self.source.replace(Some(src));
+                self.listing = None;
+                return true;
+            }
+            Self::Message::OnError(err) if err.kind == ErrorKind::NothingOpened => {
+                if self.listing.is_none() {
+                    command::list_directory(None, create_listing_handler(ctx));
+                }
+            }
+            Self::Message::OnError(err) => {
+                log::info!("{:?}", err);
+                self.notify(ctx, err.into());
+                return true;
+            }
+            Self::Message::OnNotificationExpire => {
+                self.notification = None;
+                self.notification_timer = None;
+                self.boundary = None;
+                return true;
+            }
+            Self::Message::OnListing(listing) => {
+                self.listing = Some(listing);
+                return true;
+            }
+            Self::Message::Browse(path) => {
+                command::list_directory(Some(path), create_listing_handler(ctx));
+            }
+            Self::Message::Open(path) => {
+                command::open_paths(vec![path], create_source_handler(ctx));
+                self.view_outdated = true;
+            }
+            Self::Message::OnImageLoad(e) => {
+                if let Some(img) = e.target_dyn_into::<HtmlImageElement>() {
+                    // Webviews differ in whether the natural size is oriented
+                    let natural = (img.natural_width() as f64, img.natural_height() as f64);
+                    let size = self
+                        .source
+                        .borrow()
+                        .as_ref()
+                        .and_then(stored_size)
+                        .unwrap_or(natural);
+                    self.viewport.reset_offset(size, viewport::window_size());
+                    self.image_size = Some(size);
+                    return true;
+                }
+            }
+            Self::Message::OnViewSettings(settings) => {
+                self.viewport
+                    .set_settings(settings, self.image_size, viewport::window_size());
+                return true;
+            }
+            Self::Message::OnWheel(e) => {
+                let window = viewport::window_size();
+                let anchor = (
+                    e.client_x() as f64 - window.0 / 2.0,
+                    e.client_y() as f64 - window.1 / 2.0,
+                );
+                let (zoom_in, image) = (e.delta_y() < 0.0, self.image_size);
+                self.update_view(|viewport, window| {
+                    viewport.zoom_by(zoom_in, anchor, image, window);
+                    true
+                });
+                return true;
+            }
+            // Dragging with the primary button pans the image
+            Self::Message::OnMouseMove(e) if e.buttons() & 1 != 0 => {
+                self.viewport
+                    .pan(e.movement_x() as f64, e.movement_y() as f64);
+                return true;
+            }
+            Self::Message::OnMouseMove(e) if self.viewport.pixel_art => {
+                let point = (e.client_x() as f64, e.client_y() as f64);
+                // Pixels of spreads are not read out, as the pages are scaled differently
+                let pixel = self
+                    .image_size
+                    .filter(|_| !self.is_spread())
+                    .and_then(|image| {
+                        self.viewport
+                            .pixel_at(point, image, viewport::window_size())
+                    });
+                if pixel != self.hovered_pixel {
+                    self.hovered_pixel = pixel;
+                    return true;
+                }
+            }
+            Self::Message::OnMouseMove(_) => (),
+            Self::Message::OnMouseLeave => {
+                self.hovered_pixel = None;
+                return true;
+            }
+            Self::Message::OnResize => return true,
+            Self::Message::OnOrientationSaved(Ok(_)) => {
+                // The saved file is loaded again instead of being transformed in the view
+                command::fetch_current_image_source(create_source_handler(ctx));
+                self.notify(
+                    ctx,
+                    Notification {
+                        level: "info",
+                        message: "Saved the orientation".to_string(),
+                    },
+                );
+                return true;
+            }
+            Self::Message::OnOrientationSaved(Err(err)) => {
+                self.notify(ctx, err.into());
                 return true;
             }
         }
         false
     }
 
-    fn view(&self, _ctx: &Context<Self>) -> Html {
+    fn view(&self, ctx: &Context<Self>) -> Html {
         html! {
             <div class="container">
-                <img
-                    src={self.source.borrow().clone()}
-                />
+                {
+                    match &self.listing {
+                        Some(listing) => html! {
+                            <browser::DirectoryBrowser
+                                listing={listing.clone()}
+                                on_browse={ctx.link().callback(Self::Message::Browse)}
+                                on_open={ctx.link().callback(Self::Message::Open)}
+                            />
+                        },
+                        None => html! {
+                            <div
+                                class="viewport"
+                                onwheel={ctx.link().callback(Self::Message::OnWheel)}
+                                onmousemove={ctx.link().callback(Self::Message::OnMouseMove)}
+                                onmouseleave={ctx.link().callback(|_| Self::Message::OnMouseLeave)}
+                            >
+                                { self.view_image(ctx) }
+                                {
+                                    for self
+                                        .hovered_pixel
+                                        .filter(|_| self.viewport.pixel_art)
+                                        .map(|(x, y)| html! {
+                                            <div class="pixel-readout">{ format!("x: {}, y: {}", x, y) }</div>
+                                        })
+                                }
+                            </div>
+                        },
+                    }
+                }
+                {
+                    for self.boundary.iter().map(|boundary| html! {
+                        <div class={classes!("boundary", *boundary)} />
+                    })
+                }
+                {
+                    for self.notification.iter().map(|notification| html! {
+                        <div class={classes!("notification", notification.level)}>
+                            { notification.message.clone() }
+                        </div>
+                    })
+                }
             </div>
         }
     }
@@ -83,11 +668,12 @@ impl Component for ImageViewModel {
     fn rendered(&mut self, ctx: &Context<Self>, first_render: bool) {
         if first_render {
             // Fetch first image
-            let set_source = ctx
-                .link()
-                .callback(|src: String| Self::Message::OnSourceChange(src));
+            let set_source = create_source_handler(ctx);
             command::fetch_current_image_source(set_source);
 
+            // Restore the view saved with the collection
+            command::get_view(create_view_handler(ctx));
+
             // Set key event listener
             let document = gloo::utils::document();
             let onkeydown = ctx
@@ -98,6 +684,12 @@ impl Component for ImageViewModel {
                 onkeydown.emit(event.clone());
             });
             self.keybord_listener = Some(listener);
+
+            let onresize = ctx.link().callback(|_| Self::Message::OnResize);
+            let listener = EventListener::new(&gloo::utils::window(), "resize", move |_| {
+                onresize.emit(());
+            });
+            self.resize_listener = Some(listener);
         }
     }
 }
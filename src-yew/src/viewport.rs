@@ -0,0 +1,326 @@
+use ipc::{FitMode, Transform, ViewSettings};
+
+const ZOOM_STEP: f64 = 1.25;
+const MIN_ZOOM: f64 = 0.05;
+const MAX_ZOOM: f64 = 32.0;
+// Ratio of the window size moved by a keyboard pan
+const PAN_STEP: f64 = 0.1;
+// The pixel grid is too dense to be seen below this scale
+const MIN_GRID_SCALE: f64 = 8.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewAction {
+    ZoomIn,
+    ZoomOut,
+    ResetZoom,
+    Fit(FitMode),
+    // Named after the part of the image to be shown
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    TogglePixelArt,
+    TogglePixelGrid,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    FlipHorizontal,
+    FlipVertical,
+    // Writes the rotation to the file, handled by the component
+    SaveOrientation,
+}
+
+// Width and height in pixels
+pub type Size = (f64, f64);
+
+pub fn window_size() -> Size {
+    let window = gloo::utils::window();
+    let width = window.inner_width().ok().and_then(|width| width.as_f64());
+    let height = window
+        .inner_height()
+        .ok()
+        .and_then(|height| height.as_f64());
+    (width.unwrap_or_default(), height.unwrap_or_default())
+}
+
+// Scales of the pixel-art mode are integers, or their reciprocals when zoomed out
+fn snap_scale(scale: f64) -> f64 {
+    // Avoids rounding exact integers down
+    let epsilon = 1e-9;
+    match scale >= 1.0 {
+        true => (scale + epsilon).floor(),
+        false => 1.0 / (1.0 / scale - epsilon).ceil(),
+    }
+}
+
+fn step_scale(scale: f64, zoom_in: bool) -> f64 {
+    match (scale >= 1.0, zoom_in) {
+        (true, true) => scale + 1.0,
+        (true, false) if scale > 1.0 => scale - 1.0,
+        (_, true) => 1.0 / (1.0 / scale - 1.0),
+        (_, false) => 1.0 / (1.0 / scale + 1.0),
+    }
+}
+
+// CSS applies the rightmost function first
+fn css_transform(transform: Transform) -> String {
+    format!(
+        "rotate({}deg) scaleX({})",
+        transform.quarter_turns as u32 * 90,
+        match transform.flipped {
+            true => -1,
+            false => 1,
+        }
+    )
+}
+
+// Inline styles of a page of a spread and of its image, which is turned upright in the middle
+// of the page. `share` is the ratio of the page width to the spread width.
+pub fn page_style(page: Size, share: f64, orientation: Transform) -> (String, String) {
+    let (width, height) = match orientation.is_transposed() {
+        true => (page.1 / page.0 * 100.0, page.0 / page.1 * 100.0),
+        false => (100.0, 100.0),
+    };
+    (
+        format!("width: {}%;", share * 100.0),
+        format!(
+            "width: {}%; height: {}%; transform: translate(-50%, -50%) {};",
+            width,
+            height,
+            css_transform(orientation)
+        ),
+    )
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Viewport {
+    // Saved by the backend
+    pub settings: ViewSettings,
+    // Offset of the image center from the window center in pixels
+    pub offset: (f64, f64),
+    // Nearest-neighbour scaling by integer steps
+    pub pixel_art: bool,
+    // Shown in the pixel-art mode only
+    pub pixel_grid: bool,
+    // Stored in the current image, applied before `transform`
+    pub orientation: Transform,
+    // Rotation and flip of the current image
+    pub transform: Transform,
+}
+
+impl Viewport {
+    // Replaces the saved settings, keeping the rendering mode
+    pub fn set_settings(&mut self, settings: ViewSettings, image: Option<Size>, window: Size) {
+        self.settings = settings;
+        self.offset = (0.0, 0.0);
+        if let Some(image) = image {
+            self.reset_offset(image, window);
+        }
+    }
+
+    fn displayed(&self) -> Transform {
+        self.orientation.then(self.transform)
+    }
+
+    // Size of the image as it is displayed
+    fn oriented(&self, image: Size) -> Size {
+        match self.displayed().is_transposed() {
+            true => (image.1, image.0),
+            false => image,
+        }
+    }
+
+    // Ratio of the displayed size to the natural size
+    pub fn scale(&self, image: Size, window: Size) -> f64 {
+        let image = self.oriented(image);
+        let (width_ratio, height_ratio) = (window.0 / image.0, window.1 / image.1);
+        let fitted = match self.settings.fit {
+            FitMode::Contain => width_ratio.min(height_ratio),
+            FitMode::Width => width_ratio,
+            FitMode::Height => height_ratio,
+            FitMode::Original => 1.0,
+        };
+        let scale = match fitted.is_finite() {
+            true => fitted * self.settings.zoom,
+            false => self.settings.zoom,
+        };
+        match self.pixel_art {
+            true => snap_scale(scale),
+            false => scale,
+        }
+    }
+
+    // Zooms keeping the point at `anchor`, relative to the window center, in place
+    pub fn zoom_to(&mut self, zoom: f64, anchor: (f64, f64)) {
+        let zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        let ratio = zoom / self.settings.zoom;
+        self.offset = (
+            anchor.0 - (anchor.0 - self.offset.0) * ratio,
+            anchor.1 - (anchor.1 - self.offset.1) * ratio,
+        );
+        self.settings.zoom = zoom;
+    }
+
+    pub fn zoom_by(
+        &mut self,
+        zoom_in: bool,
+        anchor: (f64, f64),
+        image: Option<Size>,
+        window: Size,
+    ) {
+        let ratio = match (self.pixel_art, image) {
+            (true, Some(image)) => {
+                let scale = self.scale(image, window);
+                step_scale(scale, zoom_in) / scale
+            }
+            _ if zoom_in => ZOOM_STEP,
+            _ => 1.0 / ZOOM_STEP,
+        };
+        self.zoom_to(self.settings.zoom * ratio, anchor);
+    }
+
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        self.offset = (self.offset.0 + dx, self.offset.1 + dy);
+    }
+
+    // Returns whether the settings to be saved have been changed
+    pub fn apply(&mut self, action: ViewAction, image: Option<Size>, window: Size) -> bool {
+        let (step_x, step_y) = (window.0 * PAN_STEP, window.1 * PAN_STEP);
+        match action {
+            ViewAction::ZoomIn => self.zoom_by(true, (0.0, 0.0), image, window),
+            ViewAction::ZoomOut => self.zoom_by(false, (0.0, 0.0), image, window),
+            ViewAction::ResetZoom => {
+                let settings = ViewSettings {
+                    zoom: 1.0,
+                    ..self.settings
+                };
+                self.set_settings(settings, image, window);
+            }
+            ViewAction::Fit(fit) => {
+                self.set_settings(ViewSettings { fit, zoom: 1.0 }, image, window)
+            }
+            ViewAction::PanLeft => self.pan(step_x, 0.0),
+            ViewAction::PanRight => self.pan(-step_x, 0.0),
+            ViewAction::PanUp => self.pan(0.0, step_y),
+            ViewAction::PanDown => self.pan(0.0, -step_y),
+            ViewAction::TogglePixelArt => self.pixel_art = !self.pixel_art,
+            ViewAction::TogglePixelGrid => self.pixel_grid = !self.pixel_grid,
+            ViewAction::RotateCw => self.set_transform(self.transform.rotate_cw(), image, window),
+            ViewAction::RotateCcw => self.set_transform(self.transform.rotate_ccw(), image, window),
+            ViewAction::Rotate180 => self.set_transform(self.transform.rotate_180(), image, window),
+            ViewAction::FlipHorizontal => {
+                self.set_transform(self.transform.flip_horizontal(), image, window)
+            }
+            ViewAction::FlipVertical => {
+                self.set_transform(self.transform.flip_vertical(), image, window)
+            }
+            ViewAction::SaveOrientation => (),
+        }
+        matches!(
+            action,
+            ViewAction::ZoomIn | ViewAction::ZoomOut | ViewAction::ResetZoom | ViewAction::Fit(_)
+        )
+    }
+
+    pub fn set_transform(&mut self, transform: Transform, image: Option<Size>, window: Size) {
+        self.transform = transform;
+        if let Some(image) = image {
+            self.reset_offset(image, window);
+        }
+    }
+
+    // Images taller than the window are shown from the top
+    pub fn reset_offset(&mut self, image: Size, window: Size) {
+        let height = self.oriented(image).1 * self.scale(image, window);
+        self.offset = (0.0, ((height - window.1) / 2.0).max(0.0));
+    }
+
+    // Center of the image in the window
+    fn center(&self, image: Size, window: Size) -> (f64, f64) {
+        let scale = self.scale(image, window);
+        let (width, height) = self.oriented(image);
+        let (width, height) = (width * scale, height * scale);
+        let left = (window.0 - width) / 2.0 + self.offset.0;
+        let top = (window.1 - height) / 2.0 + self.offset.1;
+        match self.pixel_art {
+            // Image pixels are aligned to screen pixels
+            true => (left.round() + width / 2.0, top.round() + height / 2.0),
+            false => (left + width / 2.0, top + height / 2.0),
+        }
+    }
+
+    // Left, top, width and height of the image before it is transformed
+    fn bounds(&self, image: Size, window: Size) -> (f64, f64, f64, f64) {
+        let scale = self.scale(image, window);
+        let (width, height) = (image.0 * scale, image.1 * scale);
+        let (x, y) = self.center(image, window);
+        (x - width / 2.0, y - height / 2.0, width, height)
+    }
+
+    // Inline style of the image, which is centered by the stylesheet until it is loaded
+    pub fn style(&self, image: Option<Size>, window: Size) -> String {
+        match image {
+            Some(image) => {
+                let (left, top, width, height) = self.bounds(image, window);
+                format!(
+                    "left: {}px; top: {}px; width: {}px; height: {}px; \
+                     max-width: none; max-height: none; transform: {};",
+                    left,
+                    top,
+                    width,
+                    height,
+                    css_transform(self.displayed())
+                )
+            }
+            None => String::new(),
+        }
+    }
+
+    // Inline style of the pixel grid laid over the image, if it is shown
+    pub fn grid_style(&self, image: Option<Size>, window: Size) -> Option<String> {
+        match image {
+            Some(image) if self.pixel_art && self.pixel_grid => {
+                let scale = self.scale(image, window);
+                let (left, top, width, height) = self.bounds(image, window);
+                match scale >= MIN_GRID_SCALE {
+                    true => Some(format!(
+                        "left: {}px; top: {}px; width: {}px; height: {}px; \
+                         background-size: {}px {}px; transform: {};",
+                        left,
+                        top,
+                        width,
+                        height,
+                        scale,
+                        scale,
+                        css_transform(self.displayed())
+                    )),
+                    false => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // Pixel of the upright image under `point` in the window
+    pub fn pixel_at(&self, point: (f64, f64), image: Size, window: Size) -> Option<(u32, u32)> {
+        let scale = self.scale(image, window);
+        let center = self.center(image, window);
+        let (mut x, mut y) = ((point.0 - center.0) / scale, (point.1 - center.1) / scale);
+        // Undoes the rotation and the flip by the user
+        for _ in 0..self.transform.quarter_turns {
+            (x, y) = (y, -x);
+        }
+        if self.transform.flipped {
+            x = -x;
+        }
+        let image = match self.orientation.is_transposed() {
+            true => (image.1, image.0),
+            false => image,
+        };
+        let (x, y) = (x + image.0 / 2.0, y + image.1 / 2.0);
+        match (0.0..image.0).contains(&x) && (0.0..image.1).contains(&y) {
+            true => Some((x as u32, y as u32)),
+            false => None,
+        }
+    }
+}